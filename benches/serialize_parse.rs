@@ -0,0 +1,100 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pd_driver_messages::messages::*;
+use pd_driver_messages::{serialize_msg, Parser};
+
+/// Counts allocations, to compare the arena and allocating decode paths
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations<F: FnMut()>(mut f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn parse_all(bytes: &[u8]) -> usize {
+    let mut parser = Parser::new();
+    let mut count = 0;
+    for b in bytes {
+        if let Ok(Some(_)) = parser.parse(*b) {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn parse_all_into(parser: &mut Parser, arena: &mut MessageArena, bytes: &[u8]) -> usize {
+    let mut count = 0;
+    for b in bytes {
+        if let Ok(Some(_)) = parser.parse_into(*b, arena) {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn bulk_capacitance(c: &mut Criterion) {
+    // The largest bulk capacitance message that fits in the parser's buffer
+    let msg = BulkCapacitanceStruct{ start_index: 0, values: (0..61).collect() };
+    let bytes = serialize_msg(&msg);
+
+    c.bench_function("serialize bulk capacitance", |b| {
+        b.iter(|| serialize_msg(black_box(&msg)))
+    });
+    c.bench_function("parse bulk capacitance", |b| {
+        b.iter(|| parse_all(black_box(&bytes)))
+    });
+}
+
+fn bulk_capacitance_arena(c: &mut Criterion) {
+    let msg = BulkCapacitanceStruct{ start_index: 0, values: (0..61).collect() };
+    let bytes: Vec<u8> = (0..100).flat_map(|_| serialize_msg(&msg)).collect();
+    let mut parser = Parser::new();
+    let mut arena = MessageArena::new();
+
+    let allocating = allocations(|| { parse_all(&bytes); });
+    parse_all_into(&mut parser, &mut arena, &bytes);
+    let reusing = allocations(|| { parse_all_into(&mut parser, &mut arena, &bytes); });
+    println!("allocations for 100 bulk capacitance frames: {} allocating, {} with arena", allocating, reusing);
+
+    c.bench_function("parse 100 bulk capacitance", |b| {
+        b.iter(|| parse_all(black_box(&bytes)))
+    });
+    c.bench_function("parse 100 bulk capacitance into arena", |b| {
+        b.iter(|| parse_all_into(&mut parser, &mut arena, black_box(&bytes)))
+    });
+}
+
+fn worst_case_escaping(c: &mut Criterion) {
+    // Every payload byte needs escaping
+    let msg = BulkCapacitanceStruct{ start_index: 0x7e, values: vec![0x7e7d; 61].into_iter().collect() };
+    let bytes = serialize_msg(&msg);
+
+    c.bench_function("serialize escaped bulk capacitance", |b| {
+        b.iter(|| serialize_msg(black_box(&msg)))
+    });
+    c.bench_function("parse escaped bulk capacitance", |b| {
+        b.iter(|| parse_all(black_box(&bytes)))
+    });
+}
+
+criterion_group!(benches, bulk_capacitance, bulk_capacitance_arena, worst_case_escaping);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pd_driver_messages::{serialize_raw, Parser};
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = Parser::new();
+    for b in data {
+        // Errors are fine, panics are not
+        if let Ok(Some(msg)) = parser.parse(*b) {
+            // Anything we decode must survive a trip back through the parser
+            let bytes = serialize_raw(msg.id(), &msg.payload());
+            let mut reparser = Parser::new();
+            let mut decoded = None;
+            for b in &bytes {
+                if let Some(rx) = reparser.parse(*b).expect("re-serialized message failed to parse") {
+                    decoded = Some(rx);
+                }
+            }
+            assert_eq!(decoded, Some(msg));
+        }
+    }
+});
@@ -0,0 +1,145 @@
+//! Helpers for encoding multi-byte payload fields
+//!
+//! Readers take a slice starting at the field, and writers a mutable slice
+//! starting where the field should go; both panic if the slice is too short,
+//! so callers check payload lengths first.
+
+/// Byte order of multi-byte payload fields
+///
+/// The protocol is little-endian; big-endian is supported for some messages
+/// to interoperate with firmware which encodes them that way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+pub fn read_u16(data: &[u8], endian: Endian) -> u16 {
+    match endian {
+        Endian::Little => data[0] as u16 | (data[1] as u16) << 8,
+        Endian::Big => (data[0] as u16) << 8 | data[1] as u16,
+    }
+}
+
+#[cfg(feature = "encode")]
+pub fn write_u16(out: &mut [u8], x: u16, endian: Endian) {
+    let (lo, hi) = ((x & 0xff) as u8, (x >> 8) as u8);
+    match endian {
+        Endian::Little => {
+            out[0] = lo;
+            out[1] = hi;
+        },
+        Endian::Big => {
+            out[0] = hi;
+            out[1] = lo;
+        },
+    }
+}
+
+pub fn read_i16(data: &[u8], endian: Endian) -> i16 {
+    read_u16(data, endian) as i16
+}
+
+#[cfg(feature = "encode")]
+pub fn write_i16(out: &mut [u8], x: i16, endian: Endian) {
+    write_u16(out, x as u16, endian)
+}
+
+pub fn read_u32(data: &[u8], endian: Endian) -> u32 {
+    let first = read_u16(data, endian) as u32;
+    let second = read_u16(&data[2..], endian) as u32;
+    match endian {
+        Endian::Little => first | second << 16,
+        Endian::Big => first << 16 | second,
+    }
+}
+
+#[cfg(feature = "encode")]
+pub fn write_u32(out: &mut [u8], x: u32, endian: Endian) {
+    let (lo, hi) = ((x & 0xffff) as u16, (x >> 16) as u16);
+    match endian {
+        Endian::Little => {
+            write_u16(out, lo, endian);
+            write_u16(&mut out[2..], hi, endian);
+        },
+        Endian::Big => {
+            write_u16(out, hi, endian);
+            write_u16(&mut out[2..], lo, endian);
+        },
+    }
+}
+
+pub fn read_u16_le(data: &[u8]) -> u16 {
+    read_u16(data, Endian::Little)
+}
+
+#[cfg(feature = "encode")]
+pub fn write_u16_le(out: &mut [u8], x: u16) {
+    write_u16(out, x, Endian::Little)
+}
+
+#[cfg(feature = "alloc")]
+pub fn read_i16_le(data: &[u8]) -> i16 {
+    read_i16(data, Endian::Little)
+}
+
+#[cfg(feature = "encode")]
+pub fn write_i16_le(out: &mut [u8], x: i16) {
+    write_i16(out, x, Endian::Little)
+}
+
+pub fn read_u32_le(data: &[u8]) -> u32 {
+    read_u32(data, Endian::Little)
+}
+
+#[cfg(feature = "encode")]
+pub fn write_u32_le(out: &mut [u8], x: u32) {
+    write_u32(out, x, Endian::Little)
+}
+
+#[cfg(all(test, feature = "encode"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u16() {
+        let mut buf = [0u8; 2];
+        write_u16_le(&mut buf, 0x1234);
+        assert_eq!(buf, [0x34, 0x12]);
+        assert_eq!(read_u16_le(&buf), 0x1234);
+    }
+
+    #[test]
+    fn test_i16() {
+        let mut buf = [0u8; 2];
+        write_i16_le(&mut buf, -2);
+        assert_eq!(buf, [0xfe, 0xff]);
+        assert_eq!(read_i16_le(&buf), -2);
+        write_i16_le(&mut buf, i16::MIN);
+        assert_eq!(buf, [0x00, 0x80]);
+        assert_eq!(read_i16_le(&buf), i16::MIN);
+    }
+
+    #[test]
+    fn test_u32() {
+        let mut buf = [0u8; 4];
+        write_u32_le(&mut buf, 0x12345678);
+        assert_eq!(buf, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(read_u32_le(&buf), 0x12345678);
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let mut buf = [0u8; 4];
+        write_u16(&mut buf, 0x1234, Endian::Big);
+        assert_eq!(buf[..2], [0x12, 0x34]);
+        assert_eq!(read_u16(&buf, Endian::Big), 0x1234);
+        write_i16(&mut buf, -2, Endian::Big);
+        assert_eq!(buf[..2], [0xff, 0xfe]);
+        assert_eq!(read_i16(&buf, Endian::Big), -2);
+        write_u32(&mut buf, 0x12345678, Endian::Big);
+        assert_eq!(buf, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(read_u32(&buf, Endian::Big), 0x12345678);
+    }
+}
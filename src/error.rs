@@ -1,11 +1,27 @@
 use core::fmt;
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ParseError {
     SizeOverrun,
-    ChecksumError(u16, u16),
+    ChecksumError { id: u8, found: u16, expected: u16 },
+    /// The checksum matched but the CRC32 trailer didn't
+    CrcError { id: u8, found: u32, expected: u32 },
     UnknownPacketId(u8),
-    DeserializationError
+    DeserializationError { id: u8, expected: usize, actual: usize },
+    BufferTooSmall,
+    /// Payload had the right length but carried a value the message doesn't allow
+    InvalidValue { id: u8 },
+    /// Escaped data ended with an escape byte
+    DanglingEscape,
+    /// A frame carried more payload than its id allows
+    PayloadTooLong { id: u8, expected: usize, actual: usize },
+    /// The serial port being read reported an error
+    ReadError,
+    /// A partial frame was abandoned after no bytes arrived for the timeout
+    Timeout,
+    /// The input ended part way through a frame
+    TruncatedFrame,
 }
 
 impl fmt::Display for ParseError {
@@ -16,15 +32,67 @@ impl fmt::Display for ParseError {
             SizeOverrun => {
                 write!(f, "Tried to parse packet longer than max length")
             },
-            ChecksumError(found, exp) => {
-                write!(f, "Mismatched checksum. Found {:x}, expected {:x}", found, exp)
+            ChecksumError { id, found, expected } => {
+                write!(f, "Mismatched checksum on packet id 0x{:x}. Found {:x}, expected {:x}", id, found, expected)
+            },
+            CrcError { id, found, expected } => {
+                write!(f, "Mismatched CRC32 on packet id 0x{:x}. Found {:x}, expected {:x}", id, found, expected)
             },
             UnknownPacketId(id) => {
                 write!(f, "Found unrecognized packet id 0x{:x}", id)
             },
-            DeserializationError => {
-                write!(f, "Failed parsing payload into packet struct")
+            DeserializationError { id, expected, actual } => {
+                write!(f, "Failed parsing payload for packet id 0x{:x}. Expected {} bytes, found {}", id, expected, actual)
+            },
+            BufferTooSmall => {
+                write!(f, "Output buffer too small for serialized data")
+            },
+            InvalidValue { id } => {
+                write!(f, "Invalid field value in payload for packet id 0x{:x}", id)
+            },
+            DanglingEscape => {
+                write!(f, "Escaped data ended with an escape byte")
+            },
+            PayloadTooLong { id, expected, actual } => {
+                write!(f, "Payload too long for packet id 0x{:x}. Expected {} bytes, found {}", id, expected, actual)
+            },
+            ReadError => {
+                write!(f, "Error reading from serial port")
+            },
+            Timeout => {
+                write!(f, "Timed out waiting for the rest of a frame")
+            },
+            TruncatedFrame => {
+                write!(f, "Input ended part way through a frame")
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::string::String;
+
+    #[test]
+    fn test_display_distinct() {
+        let errors = [
+            ParseError::SizeOverrun,
+            ParseError::ChecksumError { id: 1, found: 2, expected: 3 },
+            ParseError::CrcError { id: 1, found: 2, expected: 3 },
+            ParseError::UnknownPacketId(1),
+            ParseError::DeserializationError { id: 1, expected: 2, actual: 3 },
+            ParseError::BufferTooSmall,
+            ParseError::InvalidValue { id: 1 },
+            ParseError::DanglingEscape,
+            ParseError::PayloadTooLong { id: 1, expected: 2, actual: 3 },
+            ParseError::ReadError,
+            ParseError::Timeout,
+            ParseError::TruncatedFrame,
+        ];
+        let messages: BTreeSet<String> = errors.iter().map(|e| format!("{}", e)).collect();
+        assert_eq!(messages.len(), errors.len());
+        assert!(messages.iter().all(|m| !m.is_empty()));
+    }
+}
@@ -1,31 +1,117 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(all(feature = "decode", not(feature = "alloc"), not(feature = "bounded-bulk")))]
+compile_error!("decoding without `alloc` needs `bounded-bulk` for bulk capacitance messages");
+
+#[cfg(all(feature = "decode", feature = "alloc"))]
+use self::alloc::boxed::Box;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
 use self::alloc::vec::Vec;
 pub mod messages;
+mod codec;
 mod error;
 
+#[cfg(any(feature = "encode", feature = "decode"))]
 use messages::*;
-use error::ParseError;
+pub use codec::Endian;
+pub use error::ParseError;
 
 const MAX_MESSAGE_SIZE: usize = 128;
 
+/// Bytes buffered per frame, which leaves room for a CRC32 trailer on top of
+/// the largest frame
+#[cfg(all(feature = "decode", feature = "crc32"))]
+const BUFFER_SIZE: usize = MAX_MESSAGE_SIZE + 4;
+#[cfg(all(feature = "decode", not(feature = "crc32")))]
+const BUFFER_SIZE: usize = MAX_MESSAGE_SIZE;
+
+/// Returns true if a frame with `payload_len` payload bytes fits in the
+/// parser's buffer, along with the id, an optional sequence byte and the
+/// checksum
+///
+/// The buffer has separate room for the CRC32 trailer of `Parser::with_crc32`.
+pub const fn fits_in_buffer(payload_len: usize) -> bool {
+    payload_len + 4 <= MAX_MESSAGE_SIZE
+}
+
+/// Returns `size`, failing const evaluation if a payload that long doesn't
+/// pass `fits_in_buffer`
+///
+/// Fixed-size messages define their `SIZE` with this and return it from
+/// `message_size`, so one which is too large for the parser fails the build:
+///
+/// ```compile_fail
+/// use pd_driver_messages::messages::*;
+/// // 1600 electrodes would need a 200 byte payload
+/// let _ = ElectrodeEnableN::<200>::message_size(&[]);
+/// ```
+pub const fn fixed_payload_size(size: usize) -> usize {
+    assert!(fits_in_buffer(size), "fixed-size message too large for parser buffer");
+    size
+}
+
+#[cfg(feature = "decode")]
+#[derive(Clone)]
 pub struct WorkingBuffer {
     count: usize,
-    buffer: [u8; MAX_MESSAGE_SIZE],
+    /// Bytes before the payload: the id, plus a sequence byte if enabled
+    header: usize,
+    /// Bytes after the payload: the checksum, plus a CRC32 if enabled
+    trailer: usize,
+    buffer: [u8; BUFFER_SIZE],
+    /// Checksum of every byte except the trailer, updated on each push
+    running: Checksum,
+    /// Payload length of an id this crate doesn't know, from a
+    /// `DecoderRegistry`
+    custom_size: Option<usize>,
+    /// Payload length used for one id in place of the size this crate gives
+    /// it, set with `Parser::with_payload_size`
+    size_override: Option<(u8, usize)>,
+}
+
+/// Arithmetic used to accumulate the two check bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Sums modulo 256, as the protocol specifies
+    #[default]
+    Wrapping,
+    /// Sums modulo 255, as in the classic Fletcher-16, for interoperating
+    /// with firmware which computes it that way
+    Fletcher16,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Checksum {
     pub a: u8,
     pub b: u8,
+    pub mode: ChecksumMode,
 }
 
 impl Checksum {
+    pub fn new(mode: ChecksumMode) -> Checksum {
+        Checksum{ a: 0, b: 0, mode }
+    }
+
     pub fn add_byte(&mut self, x: u8) {
-        self.a = self.a.wrapping_add(x);
-        self.b = self.b.wrapping_add(self.a);
+        match self.mode {
+            ChecksumMode::Wrapping => {
+                self.a = self.a.wrapping_add(x);
+                self.b = self.b.wrapping_add(self.a);
+            },
+            ChecksumMode::Fletcher16 => {
+                self.a = ((self.a as u16 + x as u16) % 255) as u8;
+                self.b = ((self.b as u16 + self.a as u16) % 255) as u8;
+            },
+        }
+    }
+
+    pub fn add_bytes(&mut self, data: &[u8]) {
+        for x in data {
+            self.add_byte(*x);
+        }
     }
 
     pub fn get(&self) -> (u8, u8) {
@@ -33,14 +119,59 @@ impl Checksum {
     }
 }
 
-pub fn checksum(data: &[u8]) -> (u8, u8) {
-    let mut chk = Checksum::default();
-    for x in data {
-        chk.add_byte(*x);
+/// Fold bytes from any iterator, e.g. a header and payload chained together
+impl Extend<u8> for Checksum {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        for x in iter {
+            self.add_byte(x);
+        }
     }
+}
+
+impl<'a> Extend<&'a u8> for Checksum {
+    fn extend<I: IntoIterator<Item = &'a u8>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
+impl core::iter::FromIterator<u8> for Checksum {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut chk = Checksum::default();
+        chk.extend(iter);
+        chk
+    }
+}
+
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    checksum_with(ChecksumMode::Wrapping, data)
+}
+
+/// Checksum of `data` using the given arithmetic
+pub fn checksum_with(mode: ChecksumMode, data: &[u8]) -> (u8, u8) {
+    let mut chk = Checksum::new(mode);
+    chk.add_bytes(data);
     chk.get()
 }
 
+#[cfg(feature = "crc32")]
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+/// The standard CRC-32 (as used by Ethernet and zlib) of `data`
+#[cfg(feature = "crc32")]
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+#[cfg(feature = "decode")]
 impl<'a> WorkingBuffer {
     pub fn msg_id(&self) -> Option<u8> {
         if self.count > 0 {
@@ -50,49 +181,144 @@ impl<'a> WorkingBuffer {
         }
     }
 
+    /// All bytes received so far
+    pub fn as_slice(&'a self) -> &'a [u8] {
+        &self.buffer[..self.count]
+    }
+
+    /// The sequence byte following the id, if sequencing is enabled
+    pub fn seq(&self) -> Option<u8> {
+        if self.header > 1 && self.count > 1 {
+            Some(self.buffer[1])
+        } else {
+            None
+        }
+    }
+
+    /// The payload of a complete frame: everything between the header and the
+    /// checksum bytes
+    ///
+    /// Until at least the header and checksum have been received (3 bytes
+    /// without sequencing) this is empty. A frame of exactly that length has a
+    /// legitimately empty payload.
     pub fn payload(&'a self) -> &'a [u8] {
-        if self.count >= 3 {
-            &self.buffer[1..self.count - 2]
+        if self.count >= self.header + self.trailer {
+            &self.buffer[self.header..self.count - self.trailer]
         } else {
             &self.buffer[0..0]
         }
     }
 
+    /// Copy of `payload`, for queueing it once the buffer moves on to the
+    /// next frame
+    #[cfg(feature = "alloc")]
+    pub fn payload_vec(&self) -> Vec<u8> {
+        self.payload().to_vec()
+    }
+
+    /// All bytes received after the header so far, including any checksum bytes
+    ///
+    /// Unlike `payload`, this makes no assumption that the frame is complete,
+    /// so it can be used to inspect a frame during assembly.
+    pub fn partial_payload(&'a self) -> &'a [u8] {
+        if self.count >= self.header {
+            &self.buffer[self.header..self.count]
+        } else {
+            &self.buffer[0..0]
+        }
+    }
+
+    /// The two bytes following the payload, which are the checksum once the
+    /// frame is complete, or (0, 0) if there aren't enough bytes for a frame yet
+    ///
+    /// These are the last two bytes received, unless a CRC32 follows them.
     pub fn checksum(&self) -> (u8, u8) {
-        if self.count < 3 {
+        if self.count < self.header + self.trailer {
             (0, 0)
         } else {
-            let a = self.buffer[self.count - 2];
-            let b = self.buffer[self.count - 1];
+            let a = self.buffer[self.count - self.trailer];
+            let b = self.buffer[self.count - self.trailer + 1];
             (a, b)
         }
     }
 
+    /// Checksum of everything before the checksum bytes, or (0, 0) if there
+    /// aren't enough bytes for a frame yet
     pub fn calc_checksum(&self) -> (u8, u8) {
-        if self.count > 0 {
-            checksum(&self.buffer[0..self.count-2])
+        if self.count >= self.header + self.trailer {
+            checksum_with(self.running.mode, &self.buffer[0..self.count - self.trailer])
         } else {
             (0, 0)
         }
     }
 
+    /// Checksum of everything before the checksum bytes, maintained as bytes
+    /// are pushed
+    ///
+    /// Matches `calc_checksum` once there are enough bytes for a frame, without
+    /// walking the buffer again.
+    pub fn running_checksum(&self) -> (u8, u8) {
+        self.running.get()
+    }
+
+    /// Total frame length (header, payload and checksum) implied by the bytes
+    /// received so far, or None if it isn't known yet
+    pub fn declared_len(&self) -> Option<usize> {
+        let msg_id = self.msg_id()?;
+        self.payload_size(msg_id, self.partial_payload()).map(|size| size + self.header + self.trailer)
+    }
+
+    fn payload_size(&self, msg_id: u8, data: &[u8]) -> Option<usize> {
+        match (self.size_override, self.custom_size) {
+            (Some((id, size)), _) if id == msg_id => Some(size),
+            (_, Some(size)) if MessageId::from_id(msg_id).is_none() => Some(size),
+            _ => Message::message_size(msg_id, data),
+        }
+    }
+
+    /// True if `msg_id` has a payload length other than the one this crate
+    /// gives it
+    fn is_resized(&self, msg_id: u8, data: &[u8]) -> bool {
+        match self.size_override {
+            Some((id, size)) => id == msg_id && Message::message_size(msg_id, data) != Some(size),
+            None => false,
+        }
+    }
+
+    /// Returns true once the buffer holds the header, the full payload expected
+    /// for the id, and the checksum bytes
+    ///
+    /// Frames are only delimited by their start byte, so a frame whose payload
+    /// was truncated in transit can't be detected here; it is discarded when
+    /// the next start-of-frame arrives (see `ParserEvent::Resync`).
     pub fn is_complete(&self) -> bool {
         let msg_id = match self.msg_id() {
             Some(id) => id,
             None => return false,
         };
-        let expected_payload_size = Message::message_size(msg_id, self.payload());
-        // Expect payload + 1 type + 2 checksum bytes
-        if expected_payload_size.is_some() && self.count == expected_payload_size.unwrap() + 3 {
-            true
-        } else {
-            false
+        let expected_payload_size = self.payload_size(msg_id, self.payload());
+        // Expect header + payload + checksum
+        match expected_payload_size {
+            Some(size) if self.count == size + self.header + self.trailer => return true,
+            None => return false,
+            _ => (),
+        }
+        // A shorter legacy form is only taken to be complete if its checksum
+        // matches, since it's otherwise the start of the full form
+        match Message::min_message_size(msg_id, self.payload()) {
+            Some(min) if Some(min) != expected_payload_size && !self.is_resized(msg_id, self.payload()) => {
+                self.count == min + self.header + self.trailer && self.checksum() == self.running_checksum()
+            },
+            _ => false,
         }
     }
 
     pub fn push(&mut self, byte: u8) -> Result<(), ParseError> {
-        if self.count < MAX_MESSAGE_SIZE {
+        if self.count < BUFFER_SIZE {
             self.buffer[self.count] = byte;
+            if self.count >= self.trailer {
+                self.running.add_byte(self.buffer[self.count - self.trailer]);
+            }
             self.count += 1;
             Ok(())
         } else {
@@ -102,14 +328,41 @@ impl<'a> WorkingBuffer {
 
     pub fn reset(&mut self) {
         self.count = 0;
+        self.running = Checksum::new(self.running.mode);
+        self.custom_size = None;
     }
 
     pub fn new() -> WorkingBuffer {
-        WorkingBuffer{count: 0, buffer: [0; MAX_MESSAGE_SIZE]}
+        WorkingBuffer{
+            count: 0, header: 1, trailer: 2, buffer: [0; BUFFER_SIZE], running: Checksum::default(), custom_size: None,
+            size_override: None,
+        }
+    }
+}
+
+/// Renders the bytes received so far as space-separated hex, for debugging
+#[cfg(feature = "decode")]
+impl core::fmt::Display for WorkingBuffer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, b) in self.as_slice().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "decode")]
+impl Default for WorkingBuffer {
+    fn default() -> Self {
+        WorkingBuffer::new()
     }
 }
 
 /// Get transmittable bytes for msg
+#[cfg(feature = "encode")]
 pub fn serialize_msg<T>(msg: &T) -> Vec<u8>
 where
     T: MessageStruct
@@ -119,193 +372,3001 @@ where
     serialize_raw(id, &payload)
 }
 
+#[cfg(feature = "encode")]
 pub fn serialize_raw(id: u8, payload: &[u8]) -> Vec<u8> {
-    fn escaped_push(b: u8, buf: &mut Vec<u8>) {
-        if b == 0x7d || b == 0x7e {
-            buf.push(0x7d);
-            buf.push(b ^ 0x20);
-        } else {
-            buf.push(b);
-        }
-    }
+    serialize_raw_with_config(&FrameConfig::DEFAULT, id, payload)
+}
+
+/// Get transmittable bytes for a raw payload, byte-stuffed with custom
+/// delimiter and escape bytes
+#[cfg(feature = "encode")]
+pub fn serialize_raw_with_config(config: &FrameConfig, id: u8, payload: &[u8]) -> Vec<u8> {
+    stuff_frame(config, Some(ChecksumMode::Wrapping), id, payload)
+}
+
+#[cfg(feature = "encode")]
+fn stuff_frame(config: &FrameConfig, mode: Option<ChecksumMode>, id: u8, payload: &[u8]) -> Vec<u8> {
     // We don't know the size required yet, but we know it will be *at least* this much
     let mut buf = Vec::with_capacity(payload.len() + 4);
-    let mut chk = Checksum::default();
-    buf.push(0x7e); // Start of frame
-    escaped_push(id, &mut buf);
-    chk.add_byte(id);
-    for b in payload {
-        escaped_push(*b, &mut buf);
-        chk.add_byte(*b);
-    }
-    let (chk_a, chk_b) = chk.get();
-    escaped_push(chk_a, &mut buf);
-    escaped_push(chk_b, &mut buf);
+    let result = stuff_frame_with(config, mode, id, payload, |bytes| {
+        buf.extend_from_slice(bytes);
+        Ok::<(), core::convert::Infallible>(())
+    });
+    match result {
+        Ok(()) => buf,
+        Err(e) => match e {},
+    }
+}
+
+/// Byte-stuff a frame, passing `put` the start byte and then each byte of the
+/// id, payload and checksum as sent, i.e. either the byte or its escape pair
+///
+/// The checksum is left off if `mode` is None.
+#[cfg(feature = "encode")]
+fn stuff_frame_with<E>(
+    config: &FrameConfig,
+    mode: Option<ChecksumMode>,
+    id: u8,
+    payload: &[u8],
+    mut put: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut chk = mode.map(Checksum::new);
+    put(&[config.sof])?; // Start of frame
+    for b in core::iter::once(&id).chain(payload) {
+        let (bytes, len) = config.escaped(*b);
+        put(&bytes[..len])?;
+        if let Some(chk) = chk.as_mut() {
+            chk.add_byte(*b);
+        }
+    }
+    if let Some(chk) = chk {
+        let (chk_a, chk_b) = chk.get();
+        for b in [chk_a, chk_b] {
+            let (bytes, len) = config.escaped(b);
+            put(&bytes[..len])?;
+        }
+    }
+    Ok(())
+}
+
+/// Get transmittable bytes for msg with a CRC32 trailer, for a parser built
+/// `with_crc32`
+#[cfg(all(feature = "encode", feature = "crc32"))]
+pub fn serialize_msg_crc32<T>(msg: &T) -> Vec<u8>
+where
+    T: MessageStruct
+{
+    serialize_raw_crc32(msg.id(), &msg.payload())
+}
+
+/// Get transmittable bytes for a raw payload, with a CRC32 of the id and
+/// payload following the checksum
+///
+/// The CRC is little-endian and escaped like the rest of the frame. It isn't
+/// understood by parsers without `with_crc32`, so only use it when both ends
+/// agree.
+#[cfg(all(feature = "encode", feature = "crc32"))]
+pub fn serialize_raw_crc32(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = serialize_raw(id, payload);
+    let crc = !crc32_update(crc32_update(!0, &[id]), payload);
+    escape_into(&crc.to_le_bytes(), &mut buf);
     buf
 }
 
-pub struct Parser {
-    parsing: bool,
-    escaping: bool,
-    buffer: WorkingBuffer,
+/// Get a byte-stuffed frame without its checksum, for hardware which computes
+/// the check bytes itself
+///
+/// Returns the frame and the index at which the checksum goes, which is the end
+/// of the frame. The checksum covers the id and payload before escaping; once
+/// computed, append it with `finalize_checksum`.
+#[cfg(feature = "encode")]
+pub fn serialize_raw_no_checksum(id: u8, payload: &[u8]) -> (Vec<u8>, usize) {
+    let buf = stuff_frame(&FrameConfig::DEFAULT, None, id, payload);
+    let checksum_pos = buf.len();
+    (buf, checksum_pos)
 }
 
-impl Parser {
-    pub fn new() -> Parser {
-        Parser{
-            buffer: WorkingBuffer::new(),
-            parsing: false,
-            escaping: false,
-        }
+/// Append the two check bytes to a frame from `serialize_raw_no_checksum`,
+/// escaping them as needed
+#[cfg(feature = "encode")]
+pub fn finalize_checksum(frame: &mut Vec<u8>, (chk_a, chk_b): (u8, u8)) {
+    FrameConfig::DEFAULT.escape_into(&[chk_a, chk_b], frame);
+}
+
+/// Append `data` to `out` with the standard byte-stuffing applied
+///
+/// This is the escaping used within a frame, for transports which handle
+/// framing themselves; no start-of-frame byte is added.
+#[cfg(feature = "encode")]
+pub fn escape_into(data: &[u8], out: &mut Vec<u8>) {
+    FrameConfig::DEFAULT.escape_into(data, out)
+}
+
+/// Reverse `escape_into`
+///
+/// Returns `DanglingEscape` if `data` ends with an escape byte.
+#[cfg(all(feature = "decode", feature = "alloc"))]
+pub fn unescape(data: &[u8]) -> Result<Vec<u8>, ParseError> {
+    FrameConfig::DEFAULT.unescape(data)
+}
+
+/// Check the checksum of an unescaped frame, i.e. `[id][payload][chk_a][chk_b]`
+/// without the start of frame, returning its id and payload
+///
+/// Frames too short to hold an id and checksum are a `TruncatedFrame`.
+#[cfg(feature = "decode")]
+pub fn verify_frame(frame: &[u8]) -> Result<(u8, &[u8]), ParseError> {
+    if frame.len() < 3 {
+        return Err(ParseError::TruncatedFrame);
+    }
+    let (body, chk) = frame.split_at(frame.len() - 2);
+    let (exp_a, exp_b) = checksum(body);
+    if (chk[0], chk[1]) != (exp_a, exp_b) {
+        return Err(ParseError::ChecksumError {
+            id: body[0],
+            found: (chk[0] as u16) + (chk[1] as u16) * 256,
+            expected: (exp_a as u16) + (exp_b as u16) * 256,
+        });
     }
+    Ok((body[0], &body[1..]))
+}
 
-    pub fn reset(&mut self) {
-        self.escaping = false;
-        self.parsing = false;
-        self.buffer.reset();
+/// Verify a buffer of complete byte-stuffed frames, yielding the id and
+/// unescaped payload (or error) of each
+///
+/// The buffer is split on start of frame bytes; empty chunks between them are
+/// skipped. Payloads are only checksummed, not decoded.
+#[cfg(all(feature = "decode", feature = "alloc"))]
+pub fn verify_frames(buf: &[u8]) -> impl Iterator<Item = Result<(u8, Vec<u8>), ParseError>> + '_ {
+    buf.split(|b| *b == FrameConfig::DEFAULT.sof)
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| {
+            let frame = unescape(chunk)?;
+            verify_frame(&frame).map(|(id, payload)| (id, payload.to_vec()))
+        })
+}
+
+/// Build a byte-stuffed frame as a `[u8; N]` at compile time
+///
+/// Takes an id and a list of payload bytes, and produces the same bytes as
+/// `serialize_raw`. Usable anywhere a constant is, e.g. for canned test frames:
+///
+/// ```
+/// use pd_driver_messages::{frame, serialize_raw};
+/// const PING: &[u8] = &frame!(6, [1, 0x7e, 3]);
+/// assert_eq!(PING, &serialize_raw(6, &[1, 0x7e, 3])[..]);
+/// ```
+#[cfg(feature = "encode")]
+#[macro_export]
+macro_rules! frame {
+    ($id:expr, [$($b:expr),* $(,)?]) => {{
+        const ID: u8 = $id;
+        const PAYLOAD: &[u8] = &[$($b),*];
+        const LEN: usize = $crate::framed_len(ID, PAYLOAD);
+        const FRAME: [u8; LEN] = $crate::__const_frame::<LEN>(ID, PAYLOAD);
+        FRAME
+    }};
+}
+
+#[cfg(feature = "encode")]
+const fn const_checksum(id: u8, payload: &[u8]) -> (u8, u8) {
+    let mut a = id;
+    let mut b = id;
+    let mut i = 0;
+    while i < payload.len() {
+        a = a.wrapping_add(payload[i]);
+        b = b.wrapping_add(a);
+        i += 1;
     }
+    (a, b)
+}
 
-    pub fn parse(&mut self, byte: u8) -> Result<Option<Message>, ParseError> {
-        let mut byte = byte;
-        if self.escaping {
-            byte = byte ^ 0x20;
-            self.escaping = false;
-        } else if byte == 0x7d {
-            self.escaping = true;
-            return Ok(None);
-        } else if byte == 0x7e {
-            // start of frame
-            self.reset();
-            return Ok(None);
-        }
+#[cfg(feature = "encode")]
+const fn const_escaped_len(b: u8) -> usize {
+    if b == 0x7d || b == 0x7e { 2 } else { 1 }
+}
 
-        if let Err(_e) = self.buffer.push(byte) {
-            self.reset();
-            return Ok(None);
-        }
+#[cfg(feature = "encode")]
+const fn const_escaped_put<const N: usize>(mut out: [u8; N], pos: usize, b: u8) -> ([u8; N], usize) {
+    if b == 0x7d || b == 0x7e {
+        out[pos] = 0x7d;
+        out[pos + 1] = b ^ 0x20;
+        (out, pos + 2)
+    } else {
+        out[pos] = b;
+        (out, pos + 1)
+    }
+}
 
-        if self.buffer.is_complete() {
-            if self.buffer.checksum() == self.buffer.calc_checksum() {
-                let msg_id = self.buffer.msg_id().unwrap();
-                let payload = self.buffer.payload();
-                let result = Message::from_payload(msg_id, payload);
-                self.reset();
-                if result.is_ok() {
-                    return Ok(Some(result.unwrap()));
-                }
-            } else {
-                let (found_a, found_b) = self.buffer.checksum();
-                let (exp_a, exp_b) = self.buffer.calc_checksum();
-                let found = (found_a as u16) + (found_b as u16) * 256;
-                let exp = (exp_a as u16) + (exp_b as u16) * 256;
-                self.reset();
-                return Err(ParseError::ChecksumError(found, exp));
-            }
-        } 
-        Ok(None)
+/// Exact number of bytes `serialize_raw` produces for a frame, counting the
+/// escaped bytes, without building it
+///
+/// The exact counterpart to `serialized_max_len`, e.g. for sizing a DMA
+/// transfer.
+#[cfg(feature = "encode")]
+pub const fn framed_len(id: u8, payload: &[u8]) -> usize {
+    let (chk_a, chk_b) = const_checksum(id, payload);
+    let mut len = 1 + const_escaped_len(id) + const_escaped_len(chk_a) + const_escaped_len(chk_b);
+    let mut i = 0;
+    while i < payload.len() {
+        len += const_escaped_len(payload[i]);
+        i += 1;
     }
+    len
 }
 
-#[cfg(test)]
-#[macro_use]
-extern crate std;
+/// Used by `frame!`
+#[doc(hidden)]
+#[cfg(feature = "encode")]
+pub const fn __const_frame<const N: usize>(id: u8, payload: &[u8]) -> [u8; N] {
+    let out = [0x7e; N]; // Start of frame, and the rest is overwritten
+    let (mut out, mut pos) = const_escaped_put(out, 1, id);
+    let mut i = 0;
+    while i < payload.len() {
+        let (o, p) = const_escaped_put(out, pos, payload[i]);
+        out = o;
+        pos = p;
+        i += 1;
+    }
+    let (chk_a, chk_b) = const_checksum(id, payload);
+    let (out, pos) = const_escaped_put(out, pos, chk_a);
+    let (out, _) = const_escaped_put(out, pos, chk_b);
+    out
+}
 
-#[cfg(test)]
-mod tests {
-    use crate::alloc::vec;
-    use crate::alloc::vec::Vec;
-    use crate::Parser;
-    use crate::Message;
-    use crate::ParseError;
-    fn append_checksum(data: &mut Vec<u8>) {
-        use crate::checksum;
-        let (chk_a, chk_b) = checksum(&data[1..data.len()]);
-        std::println!("Appending {:x} {:x} to packet", chk_a, chk_b);
-        data.append(&mut vec![chk_a, chk_b]);
+/// Get transmittable bytes for several messages, back to back
+///
+/// Each frame starts with its own delimiter, so the parser separates them
+/// again on the receiving side.
+#[cfg(feature = "encode")]
+pub fn serialize_all(msgs: &[Message]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(msgs.iter().map(|m| m.serialized_max_len()).sum());
+    for msg in msgs {
+        buf.extend(serialize_raw(msg.id(), &msg.payload()));
     }
+    buf
+}
 
-    fn parse_message(parser: &mut Parser, data: &[u8]) -> Result<Option<Message>, ParseError> {
+/// Get transmittable bytes for msg, written into `out` without allocating
+///
+/// Returns the number of bytes written, or `BufferTooSmall` if the frame does
+/// not fit in `out`.
+#[cfg(feature = "encode")]
+pub fn serialize_msg_into<T>(msg: &T, out: &mut [u8]) -> Result<usize, ParseError>
+where
+    T: MessageStruct
+{
+    let mut payload = [0u8; MAX_MESSAGE_SIZE];
+    let len = msg.payload_into(&mut payload)?;
+    serialize_raw_into(msg.id(), &payload[..len], out)
+}
+
+/// Get transmittable bytes for msg in a fixed-capacity `heapless::Vec`
+///
+/// Returns `BufferTooSmall` if the frame needs more than `N` bytes.
+#[cfg(all(feature = "encode", feature = "heapless"))]
+pub fn serialize_msg_heapless<T, const N: usize>(msg: &T) -> Result<heapless::Vec<u8, N>, ParseError>
+where
+    T: MessageStruct
+{
+    let mut out = heapless::Vec::new();
+    // Can't fail: the length requested is the capacity
+    let _ = out.resize_default(N);
+    let len = serialize_msg_into(msg, &mut out)?;
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Append the transmittable bytes for msg to a fixed-capacity queue, e.g. a
+/// ring buffer feeding a DMA UART
+///
+/// Returns `BufferTooSmall` without modifying the queue if the whole frame
+/// does not fit.
+#[cfg(all(feature = "encode", feature = "heapless"))]
+pub fn encode_into_deque<const N: usize>(msg: &Message, q: &mut heapless::Deque<u8, N>) -> Result<(), ParseError> {
+    let frame = serialize_raw(msg.id(), &msg.payload());
+    if q.capacity() - q.len() < frame.len() {
+        return Err(ParseError::BufferTooSmall);
+    }
+    for b in frame {
+        // Can't fail: the space was checked above
+        let _ = q.push_back(b);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "encode")]
+pub fn serialize_raw_into(id: u8, payload: &[u8], out: &mut [u8]) -> Result<usize, ParseError> {
+    let mut pos = 0;
+    stuff_frame_with(&FrameConfig::DEFAULT, Some(ChecksumMode::Wrapping), id, payload, |bytes| {
+        let dest = out.get_mut(pos..pos + bytes.len()).ok_or(ParseError::BufferTooSmall)?;
+        dest.copy_from_slice(bytes);
+        pos += bytes.len();
+        Ok(())
+    })?;
+    Ok(pos)
+}
+
+/// Delimiter and escape bytes for byte-stuffed framing
+///
+/// Within a frame, any byte equal to `sof` or `escape` is sent as `escape`
+/// followed by the byte XORed with `xor`. The two bytes must differ, and `xor`
+/// must not map either of them onto the other or itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameConfig {
+    pub sof: u8,
+    pub escape: u8,
+    pub xor: u8,
+}
+
+impl FrameConfig {
+    /// The standard framing: 0x7e delimiter, 0x7d escape, 0x20 mask
+    pub const DEFAULT: FrameConfig = FrameConfig{ sof: 0x7e, escape: 0x7d, xor: 0x20 };
+
+    /// Append `data` to `out`, escaping any delimiter or escape bytes
+    #[cfg(feature = "encode")]
+    pub fn escape_into(&self, data: &[u8], out: &mut Vec<u8>) {
         for b in data {
-            let result = parser.parse(*b)?;
-            match result {
-                Some(msg) => return Ok(Some(msg)),
-                None => (),
-            }
+            let (bytes, len) = self.escaped(*b);
+            out.extend_from_slice(&bytes[..len]);
         }
-        Ok(None)
     }
 
-
-    #[test]
-    fn test_bulk_capacitance_parse() {
-        use crate::*;
-        let mut bytes = vec![0x7e, BULK_CAPACITANCE_ID, 0, 2, 04, 0, 05, 0];
-        append_checksum(&mut bytes);
-        let mut rxmsg = None;
-        let mut parser = Parser::new();
-        let result = parse_message(&mut parser, &bytes);
-        if result.is_err() {
-            panic!("Error while parsing: {}", result.err().unwrap());
+    /// `b` as sent within a frame, in the first `len` of the returned bytes:
+    /// the byte itself, or the escape byte and the masked byte
+    #[cfg(feature = "encode")]
+    fn escaped(&self, b: u8) -> ([u8; 2], usize) {
+        if b == self.escape || b == self.sof {
+            ([self.escape, b ^ self.xor], 2)
+        } else {
+            ([b, 0], 1)
         }
-        let result = result.unwrap();
-        if let Some(msg) = result {
-            use Message::*;
-            match msg {
-                BulkCapacitanceMsg(msg) => rxmsg = Some(msg),
-                _ => panic!("Got unexpected  messaged: {:?}", msg),
+    }
+
+    /// Reverse `escape_into`, returning `DanglingEscape` if `data` ends with an
+    /// escape byte
+    #[cfg(all(feature = "decode", feature = "alloc"))]
+    pub fn unescape(&self, data: &[u8]) -> Result<Vec<u8>, ParseError> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut escaping = false;
+        for b in data {
+            if escaping {
+                out.push(*b ^ self.xor);
+                escaping = false;
+            } else if *b == self.escape {
+                escaping = true;
+            } else {
+                out.push(*b);
             }
         }
-        assert!(rxmsg.is_some());
-        let rxmsg = rxmsg.unwrap();
-        assert_eq!(rxmsg.values.len(), 2);
-        assert_eq!(rxmsg.values[0], 4);
-        assert_eq!(rxmsg.values[1], 5);
+        if escaping {
+            return Err(ParseError::DanglingEscape);
+        }
+        Ok(out)
     }
+}
 
-    #[test]
-    fn test_active_capacitance_parse() {
-        use crate::*;
-        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
-        append_checksum(&mut bytes);
-        let mut parser = Parser::new();
-        let result = parse_message(&mut parser, &bytes);
-        if result.is_err() {
-            panic!("Error while parsing: {}", result.err().unwrap());
+impl Default for FrameConfig {
+    fn default() -> Self {
+        FrameConfig::DEFAULT
+    }
+}
+
+/// Wire framing used by `Parser` and `serialize_raw_framed`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramingMode {
+    /// `[0x7e][id][payload][chk_a][chk_b]`, with any 0x7d or 0x7e after the
+    /// start byte escaped
+    #[default]
+    ByteStuffed,
+    /// `[0x7e][len][id][payload][chk_a][chk_b]`, where `len` is the payload
+    /// length, with no escaping
+    LengthPrefixed,
+    /// `[0x00][id, payload, chk_a, chk_b][0x00]`, with the contents encoded by
+    /// Consistent Overhead Byte Stuffing so they contain no zero bytes
+    ///
+    /// COBS adds at most one byte per 254, where escaping can double the size.
+    /// Each zero both ends a frame and starts the next, so a sender may omit the
+    /// leading delimiter.
+    Cobs,
+}
+
+/// COBS-encode `data`, appending the result to `out`
+#[cfg(feature = "encode")]
+fn cobs_encode(data: &[u8], out: &mut Vec<u8>) {
+    // Each block is a code byte giving the offset to the next zero, followed
+    // by the non-zero bytes in between
+    let mut code_pos = out.len();
+    let mut code = 1u8;
+    out.push(0);
+    for b in data {
+        if *b != 0 {
+            out.push(*b);
+            code += 1;
         }
-        let result = result.unwrap();
-        if let Some(msg) = result {
-            use Message::*;
-            match msg {
-                ActiveCapacitanceMsg(msg) => {
-                    assert_eq!(msg.baseline, 0x302);
-                    assert_eq!(msg.measurement, 0x504);
-                },
-                _ => panic!("Invalid message type found"),
-            }
-        } else {
-            panic!("No message parsed");
+        if *b == 0 || code == 0xff {
+            out[code_pos] = code;
+            code_pos = out.len();
+            code = 1;
+            out.push(0);
         }
     }
+    out[code_pos] = code;
+}
 
-    #[test]
-    fn test_electrode_enable_roundtrip() {
-        use crate::*;
-        let values: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0x7d, 0x7e];
-        let tx_msg = ElectrodeEnableStruct{ values };
-        let payload: Vec<u8> = tx_msg.payload();
-        let tx_bytes = serialize_raw(ELECTRODE_ENABLE_ID, &payload);
-        let mut parser = Parser::new();
-        let result = parse_message(&mut parser, &tx_bytes);
-        if result.is_err() {
-            panic!("Error while parsing: {}", result.err().unwrap());
-        }
-        let rx_msg = result.unwrap();
-        assert!(rx_msg.is_some());
-        let rx_msg = rx_msg.unwrap();
-        if let Message::ElectrodeEnableMsg(msg) = rx_msg {
-            assert_eq!(msg.values, values);
+/// Get transmittable bytes for a raw payload using the given framing
+///
+/// # Panics
+///
+/// In length-prefixed mode the length byte limits the payload to 255 bytes,
+/// and a longer payload panics rather than producing a malformed frame.
+#[cfg(feature = "encode")]
+pub fn serialize_raw_framed(mode: FramingMode, id: u8, payload: &[u8]) -> Vec<u8> {
+    frame_with(mode, ChecksumMode::Wrapping, id, payload)
+}
+
+#[cfg(feature = "encode")]
+fn frame_with(mode: FramingMode, checksum: ChecksumMode, id: u8, payload: &[u8]) -> Vec<u8> {
+    match mode {
+        FramingMode::ByteStuffed => stuff_frame(&FrameConfig::DEFAULT, Some(checksum), id, payload),
+        FramingMode::LengthPrefixed => {
+            assert!(payload.len() <= 255, "length-prefixed payload of {} bytes exceeds 255", payload.len());
+            let mut buf = Vec::with_capacity(payload.len() + 5);
+            buf.push(0x7e); // Start of frame
+            buf.push(payload.len() as u8);
+            buf.push(id);
+            buf.extend_from_slice(payload);
+            let (chk_a, chk_b) = checksum_with(checksum, &buf[2..]);
+            buf.push(chk_a);
+            buf.push(chk_b);
+            buf
+        },
+        FramingMode::Cobs => {
+            let mut frame = Vec::with_capacity(payload.len() + 3);
+            frame.push(id);
+            frame.extend_from_slice(payload);
+            let (chk_a, chk_b) = checksum_with(checksum, &frame);
+            frame.push(chk_a);
+            frame.push(chk_b);
+            let mut buf = Vec::with_capacity(frame.len() + frame.len() / 254 + 3);
+            buf.push(0); // Start of frame
+            cobs_encode(&frame, &mut buf);
+            buf.push(0); // End of frame
+            buf
+        },
+    }
+}
+
+/// Serializer which can number the frames it produces
+///
+/// With sequencing enabled, a sequence byte follows the id in every frame,
+/// counting up from 0 and wrapping, and is covered by the checksum. It is
+/// recovered on the receiving side with `Parser::parse_seq`. In
+/// length-prefixed mode the length byte counts the sequence byte as well as
+/// the payload.
+#[cfg(feature = "encode")]
+#[derive(Clone, Debug, Default)]
+pub struct Encoder {
+    mode: FramingMode,
+    checksum: ChecksumMode,
+    sequence: bool,
+    next_seq: u8,
+}
+
+#[cfg(feature = "encode")]
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::new_with_mode(FramingMode::ByteStuffed)
+    }
+
+    pub fn new_with_mode(mode: FramingMode) -> Encoder {
+        Encoder{ mode, checksum: ChecksumMode::Wrapping, sequence: false, next_seq: 0 }
+    }
+
+    /// Compute check bytes with the given arithmetic, for a parser built with
+    /// the same `with_checksum_mode`
+    pub fn with_checksum_mode(mut self, checksum: ChecksumMode) -> Encoder {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Insert a sequence byte after the id of each frame
+    pub fn with_sequence(mut self, sequence: bool) -> Encoder {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Get transmittable bytes for msg
+    pub fn serialize_msg<T>(&mut self, msg: &T) -> Vec<u8>
+    where
+        T: MessageStruct
+    {
+        self.serialize_raw(msg.id(), &msg.payload())
+    }
+
+    /// Get transmittable bytes for a raw payload
+    ///
+    /// Panics on a payload too long for the framing, as `serialize_raw_framed`
+    /// does; the sequence byte counts towards the limit.
+    pub fn serialize_raw(&mut self, id: u8, payload: &[u8]) -> Vec<u8> {
+        if !self.sequence {
+            return frame_with(self.mode, self.checksum, id, payload);
+        }
+        // The sequence byte is framed exactly like the first payload byte
+        let mut body = Vec::with_capacity(payload.len() + 1);
+        body.push(self.next_seq);
+        body.extend_from_slice(payload);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        frame_with(self.mode, self.checksum, id, &body)
+    }
+}
+
+/// Framing-level events reported by `Parser::parse_event`
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone)]
+pub enum ParserEvent {
+    /// A start-of-frame byte was received while idle
+    FrameStart,
+    /// A complete message was decoded
+    Message(Message),
+    /// A start-of-frame byte was received mid-frame, and the partial frame was
+    /// discarded
+    Resync,
+}
+
+/// Why the parser discarded its buffer, as reported by `Parser::parse_verbose`
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetReason {
+    /// A start-of-frame byte arrived mid-frame
+    NewFrame,
+    /// The frame failed its checksum
+    ChecksumFailed,
+    /// The frame was too long for the buffer
+    Overrun,
+    /// The frame carried more payload than its id allows
+    PayloadTooLong,
+    /// The frame was abandoned after no bytes arrived for the timeout
+    TimedOut,
+    /// The frame ended part way through
+    Truncated,
+    /// The frame was complete, whether or not its payload decoded
+    Completed,
+}
+
+/// Callback given the id and payload of each frame which passes its checksum
+///
+/// Cloning gives an empty observer, since the callback can't be cloned.
+#[cfg(all(feature = "decode", feature = "alloc"))]
+#[derive(Default)]
+struct FrameObserver(Option<Box<ObserverFn>>);
+
+#[cfg(all(feature = "decode", feature = "alloc"))]
+type ObserverFn = dyn FnMut(u8, &[u8]);
+
+#[cfg(all(feature = "decode", feature = "alloc"))]
+impl Clone for FrameObserver {
+    fn clone(&self) -> Self {
+        FrameObserver(None)
+    }
+}
+
+#[cfg(feature = "decode")]
+#[derive(Clone)]
+pub struct Parser {
+    mode: FramingMode,
+    config: FrameConfig,
+    /// Set on start of frame, and cleared when the frame completes or is dropped
+    parsing: bool,
+    escaping: bool,
+    /// Count of bytes received outside of any frame
+    dropped: usize,
+    /// Number of idle ticks before a partial frame is abandoned, or 0 to never
+    /// time out
+    timeout_ticks: u32,
+    /// Ticks since the last byte was received
+    idle_ticks: u32,
+    /// Decode unknown ids as `Message::RawMessage` instead of dropping them
+    #[cfg(feature = "alloc")]
+    lenient: bool,
+    /// Reject frames whose checksum doesn't match
+    verify_checksum: bool,
+    /// Byte order used to decode multi-byte payload fields
+    endian: Endian,
+    /// Payload length read from the frame header in length-prefixed mode
+    frame_len: Option<usize>,
+    /// Payload length of a byte-stuffed frame which failed its checksum, while
+    /// watching for it to turn out longer than expected
+    ///
+    /// Only confirmed once the frame ends, so that a checksum matching by
+    /// chance part way through a longer frame isn't taken as its end.
+    overlong: Option<usize>,
+    /// Bytes left in the current COBS block
+    cobs_remaining: u8,
+    /// Whether the current COBS block ends in a zero, which is only written
+    /// once another block follows it
+    cobs_zero: bool,
+    buffer: WorkingBuffer,
+    /// Keep the contents of frames which fail their checksum
+    retain_bad_frame: bool,
+    /// The last frame to fail its checksum, if `has_bad_frame` is set
+    bad_frame: WorkingBuffer,
+    has_bad_frame: bool,
+    /// Sequence byte of the last message decoded
+    last_seq: Option<u8>,
+    /// Rescan failed length-prefixed frames for a start byte
+    fast_resync: bool,
+    /// Message completed while rescanning, to be returned with the next byte
+    pending: Option<Message>,
+    /// Set while `parse_autodetect` is collecting a raw frame
+    raw_frame: bool,
+    /// Buffers lent by a `MessageArena` during `parse_into`
+    pool: DecodePool,
+    #[cfg(feature = "alloc")]
+    observer: FrameObserver,
+}
+
+#[cfg(feature = "decode")]
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+#[cfg(feature = "decode")]
+impl Parser {
+    pub fn new() -> Parser {
+        Parser::new_with_mode(FramingMode::ByteStuffed)
+    }
+
+    pub fn new_with_mode(mode: FramingMode) -> Parser {
+        Parser{
+            mode,
+            config: FrameConfig::DEFAULT,
+            buffer: WorkingBuffer::new(),
+            parsing: false,
+            escaping: false,
+            dropped: 0,
+            timeout_ticks: 0,
+            idle_ticks: 0,
+            #[cfg(feature = "alloc")]
+            lenient: false,
+            verify_checksum: true,
+            endian: Endian::Little,
+            frame_len: None,
+            overlong: None,
+            cobs_remaining: 0,
+            cobs_zero: false,
+            retain_bad_frame: false,
+            bad_frame: WorkingBuffer::new(),
+            has_bad_frame: false,
+            last_seq: None,
+            fast_resync: false,
+            pending: None,
+            raw_frame: false,
+            pool: DecodePool::default(),
+            #[cfg(feature = "alloc")]
+            observer: FrameObserver::default(),
+        }
+    }
+
+    /// Create a byte-stuffed parser with custom delimiter and escape bytes
+    pub fn new_with_config(config: FrameConfig) -> Parser {
+        let mut parser = Parser::new();
+        parser.config = config;
+        parser
+    }
+
+    /// Keep frames with unrecognized ids as `Message::RawMessage`
+    ///
+    /// See `Message::from_payload_lenient` for the limitations with
+    /// byte-stuffed framing.
+    #[cfg(feature = "alloc")]
+    pub fn with_lenient(mut self, lenient: bool) -> Parser {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Check the checksum of each frame (the default)
+    ///
+    /// Disabling this is only meant for bringing up a link which is known to
+    /// corrupt frames; the checksum bytes are still expected, but ignored.
+    pub fn with_verify_checksum(mut self, verify: bool) -> Parser {
+        self.verify_checksum = verify;
+        self
+    }
+
+    /// Decode payloads with the given byte order (little-endian by default)
+    ///
+    /// See `Message::from_payload_endian` for which messages this applies to.
+    pub fn with_endian(mut self, endian: Endian) -> Parser {
+        self.endian = endian;
+        self
+    }
+
+    /// After a checksum failure in length-prefixed mode, look for the start of
+    /// the next frame within the failed one instead of waiting for the next
+    /// start byte
+    ///
+    /// Without escaping, a frame which lost a byte in transit swallows the
+    /// start of the frame after it; this recovers that frame. A message found
+    /// this way completes on the byte which fails the checksum; it is returned
+    /// from the next call to `parse`, or straight away by `take_recovered`.
+    ///
+    /// Byte-stuffed frames don't need this: a start byte can't appear escaped,
+    /// so every one restarts the parser and the next frame is never swallowed.
+    pub fn with_fast_resync(mut self, fast_resync: bool) -> Parser {
+        self.fast_resync = fast_resync;
+        self
+    }
+
+    /// Expect a sequence byte after the id of each frame, as written by an
+    /// `Encoder` with sequencing enabled
+    pub fn with_sequence(mut self, sequence: bool) -> Parser {
+        let header = if sequence { 2 } else { 1 };
+        self.buffer.header = header;
+        self.bad_frame.header = header;
+        self
+    }
+
+    /// Verify check bytes computed with the given arithmetic, e.g. for devices
+    /// sending a mod 255 Fletcher-16
+    pub fn with_checksum_mode(mut self, mode: ChecksumMode) -> Parser {
+        self.buffer.running = Checksum::new(mode);
+        self.bad_frame.running = Checksum::new(mode);
+        self
+    }
+
+    /// Expect a CRC32 of the id and payload after the checksum of each frame,
+    /// as written by `serialize_raw_crc32`, and reject frames where it doesn't
+    /// match
+    ///
+    /// Off by default, since frames without the trailer won't parse.
+    #[cfg(feature = "crc32")]
+    pub fn with_crc32(mut self, crc32: bool) -> Parser {
+        let trailer = if crc32 { 6 } else { 2 };
+        self.buffer.trailer = trailer;
+        self.bad_frame.trailer = trailer;
+        self
+    }
+
+    /// Expect payloads of `size` bytes for frames with `id`, in place of the
+    /// size this crate gives that id, e.g. for an `ElectrodeEnableN` with
+    /// fewer than 16 bytes
+    ///
+    /// Frames resized this way are returned as a `RawMessage`, to be decoded
+    /// with `TryFrom` on the payload. Only one id can be resized; a later call
+    /// replaces the earlier one.
+    #[cfg(feature = "alloc")]
+    pub fn with_payload_size(mut self, id: u8, size: usize) -> Parser {
+        assert!(fits_in_buffer(size), "payload too large for the parse buffer");
+        self.buffer.size_override = Some((id, size));
+        self.bad_frame.size_override = Some((id, size));
+        self
+    }
+
+    /// Call `f` with the id and payload of every frame which passes its
+    /// checksum, before it is decoded, e.g. to capture frames for replay
+    ///
+    /// Frames with unknown ids or invalid payloads are still observed. A clone
+    /// of the parser starts with no observer, since the callback can't be
+    /// cloned. The callback needn't be `Send`, so neither is `Parser`.
+    #[cfg(feature = "alloc")]
+    pub fn set_frame_observer(&mut self, f: impl FnMut(u8, &[u8]) + 'static) {
+        self.observer = FrameObserver(Some(Box::new(f)));
+    }
+
+    /// Keep the bytes of a frame which fails its checksum, so that they can be
+    /// inspected with `last_bad_frame`
+    pub fn with_retain_bad_frame(mut self, retain: bool) -> Parser {
+        self.retain_bad_frame = retain;
+        self
+    }
+
+    /// Abandon a partial frame after `ticks` calls to `tick` with no bytes
+    /// received in between
+    ///
+    /// A value of 0 (the default) disables the timeout.
+    pub fn with_timeout_ticks(mut self, ticks: u32) -> Parser {
+        self.timeout_ticks = ticks;
+        self
+    }
+
+    /// Advance the inter-byte timer, e.g. from a periodic timer interrupt
+    ///
+    /// Returns `ParseError::Timeout` when a partial frame is abandoned; callers
+    /// which don't care can ignore it, as the parser has already been reset.
+    pub fn tick(&mut self) -> Result<(), ParseError> {
+        if self.timeout_ticks == 0 || !self.parsing {
+            return Ok(());
+        }
+        self.idle_ticks += 1;
+        if self.idle_ticks >= self.timeout_ticks {
+            self.reset();
+            return Err(ParseError::Timeout);
+        }
+        Ok(())
+    }
+
+    /// The id, payload and checksum bytes (after unescaping) of the last frame
+    /// which failed its checksum
+    ///
+    /// Only available when enabled with `with_retain_bad_frame`, and cleared
+    /// when the next frame starts.
+    pub fn last_bad_frame(&self) -> Option<&[u8]> {
+        if self.has_bad_frame {
+            Some(&self.bad_frame.buffer[..self.bad_frame.count])
         } else {
-            panic!("Did not parse expected message");
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.escaping = false;
+        self.parsing = false;
+        self.frame_len = None;
+        self.overlong = None;
+        self.cobs_remaining = 0;
+        self.cobs_zero = false;
+        self.idle_ticks = 0;
+        self.raw_frame = false;
+        self.buffer.reset();
+    }
+
+    /// Framing of the frame being assembled, which differs from `mode` while
+    /// `parse_autodetect` collects a raw frame
+    fn framing(&self) -> FramingMode {
+        if self.raw_frame {
+            FramingMode::LengthPrefixed
+        } else {
+            self.mode
+        }
+    }
+
+    /// Discard any partial frame, e.g. when the connection is closed, returning
+    /// the (unescaped) bytes which had been received for it
+    pub fn drain(&mut self) -> Option<&[u8]> {
+        let count = self.buffer.count;
+        self.reset();
+        if count > 0 {
+            Some(&self.buffer.buffer[..count])
+        } else {
+            None
+        }
+    }
+
+    /// True between a start of frame and the frame completing or being dropped
+    pub fn is_parsing(&self) -> bool {
+        self.parsing
+    }
+
+    /// Check that the input ended cleanly, e.g. once the transport is closed
+    ///
+    /// Returns `TruncatedFrame` if any bytes of a frame have been received
+    /// without it completing. A start-of-frame byte on its own, or the tail of
+    /// a frame which already failed its checksum, doesn't count, though that
+    /// tail is reported as `PayloadTooLong` if its checksum matches.
+    pub fn finish(self) -> Result<(), ParseError> {
+        if let Some(e) = self.overlong_match() {
+            return Err(e);
+        }
+        let mid_frame = (self.buffer.count > 0 && self.overlong.is_none())
+            || self.escaping
+            || self.frame_len.is_some()
+            || self.cobs_remaining > 0;
+        if self.parsing && mid_frame {
+            Err(ParseError::TruncatedFrame)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Number of bytes discarded because they arrived outside of any frame
+    pub fn bytes_dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the id and bytes received so far for the frame being assembled,
+    /// or None if no frame is in progress
+    pub fn in_progress(&self) -> Option<(u8, &[u8])> {
+        self.buffer.msg_id().map(|id| (id, self.buffer.partial_payload()))
+    }
+
+    /// Parse one byte, returning a message once a frame completes
+    ///
+    /// A frame cut short by the next start-of-frame is dropped without an
+    /// error; `parse_event` and `parse_verbose` report it.
+    pub fn parse(&mut self, byte: u8) -> Result<Option<Message>, ParseError> {
+        match self.parse_event(byte)? {
+            Some(ParserEvent::Message(msg)) => Ok(Some(msg)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Parse a byte, returning each completed message along with its sequence
+    /// byte
+    ///
+    /// The sequence is None unless enabled with `with_sequence`.
+    pub fn parse_seq(&mut self, byte: u8) -> Result<Option<(Option<u8>, Message)>, ParseError> {
+        Ok(self.parse(byte)?.map(|msg| (self.last_seq, msg)))
+    }
+
+    /// Parse a byte like `parse`, decoding into buffers reused from earlier
+    /// messages rather than allocating new ones
+    ///
+    /// The message returned stays in the arena until the next one completes.
+    pub fn parse_into<'a>(&mut self, byte: u8, arena: &'a mut MessageArena) -> Result<Option<&'a Message>, ParseError> {
+        core::mem::swap(&mut self.pool, &mut arena.pool);
+        let result = self.parse(byte);
+        core::mem::swap(&mut self.pool, &mut arena.pool);
+        Ok(result?.map(move |msg| arena.store(msg)))
+    }
+
+    /// Parse a byte which may belong to either a byte-stuffed frame or a raw
+    /// frame which has already been unescaped
+    ///
+    /// While idle, a start-of-frame byte begins a byte-stuffed frame as in
+    /// `parse`, and any other byte is taken as the length of a raw
+    /// length-prefixed frame without its start byte, i.e.
+    /// `[len][id][payload][chk]`. A raw frame long enough to have a 0x7e length
+    /// wouldn't fit the buffer, so the first byte is never ambiguous. However
+    /// this is a heuristic:
+    ///
+    /// - Noise between frames is taken as the start of a raw frame, which
+    ///   swallows bytes until its checksum fails.
+    /// - Raw frames must be length-prefixed, since an unescaped frame can't be
+    ///   delimited any other way.
+    /// - Only meaningful for a parser in the default byte-stuffed mode.
+    ///
+    /// The parser's own mode isn't changed: a detected raw frame is only parsed
+    /// as length-prefixed until it ends or the parser is reset.
+    pub fn parse_autodetect(&mut self, byte: u8) -> Result<Option<Message>, ParseError> {
+        if !self.parsing && byte != self.config.sof {
+            self.raw_frame = true;
+            self.parsing = true;
+        }
+        self.parse(byte)
+    }
+
+    /// Parse a byte, decoding frames with ids this crate doesn't know using
+    /// `registry`
+    ///
+    /// Byte-stuffed frames can only be delimited for ids registered with a
+    /// payload length. Unknown ids which aren't registered are an
+    /// `UnknownPacketId`, or a `RawMessage` if the parser is lenient.
+    #[cfg(feature = "alloc")]
+    pub fn parse_with_registry<C>(
+        &mut self,
+        byte: u8,
+        registry: &DecoderRegistry<C>,
+    ) -> Result<Option<Decoded<C>>, ParseError> {
+        let lenient = core::mem::replace(&mut self.lenient, true);
+        let result = self.parse(byte);
+        self.lenient = lenient;
+        if self.buffer.count == self.buffer.header {
+            // The id just arrived
+            self.buffer.custom_size = self.buffer.msg_id().and_then(|id| registry.payload_len(id));
+        }
+        match result? {
+            Some(Message::RawMessage { id, payload }) => match registry.decode(id, &payload) {
+                Err(ParseError::UnknownPacketId(_)) if lenient => {
+                    Ok(Some(Decoded::Message(Message::RawMessage { id, payload })))
+                },
+                result => result.map(Some),
+            },
+            msg => Ok(msg.map(Decoded::Message)),
+        }
+    }
+
+    /// Read and parse bytes from a serial port until a message completes or no
+    /// more bytes are available
+    ///
+    /// Returns `WouldBlock` if no bytes were available, or `Ok(None)` if some
+    /// were parsed without completing a message. A read error discards any
+    /// partial frame.
+    #[cfg(feature = "embedded-hal")]
+    pub fn poll_read<R>(&mut self, r: &mut R) -> nb::Result<Option<Message>, ParseError>
+    where
+        R: embedded_hal::serial::Read<u8>
+    {
+        let mut progress = false;
+        loop {
+            match r.read() {
+                Ok(b) => {
+                    progress = true;
+                    if let Some(msg) = self.parse(b)? {
+                        return Ok(Some(msg));
+                    }
+                },
+                Err(nb::Error::WouldBlock) if progress => return Ok(None),
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(nb::Error::Other(_)) => {
+                    self.reset();
+                    return Err(nb::Error::Other(ParseError::ReadError));
+                },
+            }
+        }
+    }
+
+    /// Parse a byte like `parse`, also reporting why the buffer was reset if
+    /// this byte caused it to be
+    pub fn parse_verbose(&mut self, byte: u8) -> (Result<Option<Message>, ParseError>, Option<ResetReason>) {
+        match self.parse_event(byte) {
+            Ok(Some(ParserEvent::Message(msg))) => (Ok(Some(msg)), Some(ResetReason::Completed)),
+            Ok(Some(ParserEvent::Resync)) => (Ok(None), Some(ResetReason::NewFrame)),
+            Ok(_) => (Ok(None), None),
+            Err(e) => {
+                let reason = match e {
+                    ParseError::ChecksumError { .. } | ParseError::CrcError { .. } => ResetReason::ChecksumFailed,
+                    ParseError::SizeOverrun => ResetReason::Overrun,
+                    ParseError::PayloadTooLong { .. } => ResetReason::PayloadTooLong,
+                    ParseError::Timeout => ResetReason::TimedOut,
+                    ParseError::TruncatedFrame => ResetReason::Truncated,
+                    ParseError::UnknownPacketId(_)
+                    | ParseError::DeserializationError { .. }
+                    | ParseError::InvalidValue { .. } => ResetReason::Completed,
+                    // Not produced while parsing frames
+                    ParseError::BufferTooSmall | ParseError::DanglingEscape | ParseError::ReadError => {
+                        ResetReason::Completed
+                    },
+                };
+                (Err(e), Some(reason))
+            },
+        }
+    }
+
+    /// Take a message recovered by fast resync from the frame which just
+    /// failed, rather than waiting for the next call to `parse` to return it
+    pub fn take_recovered(&mut self) -> Option<Message> {
+        self.pending.take()
+    }
+
+    /// Parse a buffer of bytes, appending every complete message to `out`
+    ///
+    /// Stops at the first error, leaving the rest of `bytes` unparsed, though
+    /// a message recovered by fast resync is still appended. Any partial frame
+    /// at the end of `bytes` is kept for the next call.
+    #[cfg(feature = "alloc")]
+    pub fn feed(&mut self, bytes: &[u8], out: &mut Vec<Message>) -> Result<(), ParseError> {
+        for b in bytes {
+            match self.parse(*b) {
+                Ok(Some(msg)) => out.push(msg),
+                Ok(None) => (),
+                Err(e) => {
+                    out.extend(self.take_recovered());
+                    return Err(e);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a byte, reporting framing events as well as completed messages
+    pub fn parse_event(&mut self, byte: u8) -> Result<Option<ParserEvent>, ParseError> {
+        self.idle_ticks = 0;
+        let event = match self.framing() {
+            FramingMode::ByteStuffed => self.parse_stuffed(byte),
+            FramingMode::LengthPrefixed => self.parse_length_prefixed(byte),
+            FramingMode::Cobs => self.parse_cobs(byte),
+        }?;
+        // A message recovered by fast resync takes precedence over framing
+        // events for this byte
+        match (self.pending.take(), event) {
+            (Some(msg), Some(ParserEvent::Message(next))) => {
+                self.pending = Some(next);
+                Ok(Some(ParserEvent::Message(msg)))
+            },
+            (Some(msg), _) => Ok(Some(ParserEvent::Message(msg))),
+            (None, event) => Ok(event),
+        }
+    }
+
+    fn parse_stuffed(&mut self, byte: u8) -> Result<Option<ParserEvent>, ParseError> {
+        let FrameConfig{ sof, escape, xor } = self.config;
+        if !self.parsing && byte != sof {
+            self.dropped += 1;
+            return Ok(None);
+        }
+
+        let mut byte = byte;
+        if self.escaping {
+            byte ^= xor;
+            self.escaping = false;
+        } else if byte == escape {
+            self.escaping = true;
+            return Ok(None);
+        } else if byte == sof {
+            // start of frame
+            let mid_frame = self.buffer.count > 0 && self.overlong.is_none();
+            let overlong = self.overlong_match();
+            self.reset();
+            self.has_bad_frame = false;
+            self.parsing = true;
+            if let Some(e) = overlong {
+                return Err(e);
+            } else if mid_frame {
+                return Ok(Some(ParserEvent::Resync));
+            } else {
+                return Ok(Some(ParserEvent::FrameStart));
+            }
+        }
+
+        if self.overlong.is_some() {
+            if self.buffer.push(byte).is_err() {
+                // Too long to ever match, so give up watching
+                self.reset();
+            }
+            return Ok(None);
+        }
+
+        if let Err(e) = self.buffer.push(byte) {
+            self.reset();
+            return Err(e);
+        }
+
+        // Give up as soon as the frame is known not to fit, rather than
+        // buffering up to the limit first
+        if matches!(self.buffer.declared_len(), Some(len) if len > BUFFER_SIZE) {
+            self.reset();
+            return Err(ParseError::SizeOverrun);
+        }
+
+        if self.buffer.is_complete() {
+            return self.finish_frame();
+        }
+        Ok(None)
+    }
+
+    /// Restart parsing from the first start byte within a frame which failed
+    /// its checksum, in case the frame was truncated and that byte starts the
+    /// next one
+    fn replay_from_sof(&mut self) {
+        let count = self.buffer.count;
+        let bytes = self.buffer.buffer;
+        self.reset();
+        if let Some(start) = bytes[..count].iter().position(|b| *b == 0x7e) {
+            for b in &bytes[start..count] {
+                // Errors here belong to the failed frame, which is already reported
+                if let Ok(Some(ParserEvent::Message(msg))) = self.parse_length_prefixed(*b) {
+                    self.pending = Some(msg);
+                }
+            }
+        }
+    }
+
+    /// `PayloadTooLong` if a frame which failed its checksum at the expected
+    /// length, and then carried on, passes its checksum at the length received
+    fn overlong_match(&self) -> Option<ParseError> {
+        let expected = self.overlong?;
+        let actual = self.buffer.payload().len();
+        if actual > expected && self.buffer.checksum() == self.buffer.running_checksum() {
+            let id = self.buffer.msg_id().unwrap();
+            Some(ParseError::PayloadTooLong { id, expected, actual })
+        } else {
+            None
+        }
+    }
+
+    fn parse_length_prefixed(&mut self, byte: u8) -> Result<Option<ParserEvent>, ParseError> {
+        // Without escaping, 0x7e is only a delimiter when we aren't in a frame
+        if !self.parsing {
+            if byte == 0x7e {
+                self.reset();
+                self.has_bad_frame = false;
+                self.parsing = true;
+                return Ok(Some(ParserEvent::FrameStart));
+            }
+            self.dropped += 1;
+            return Ok(None);
+        }
+
+        let len = match self.frame_len {
+            Some(len) => len,
+            None => {
+                if byte as usize + 1 + self.buffer.trailer > BUFFER_SIZE {
+                    self.reset();
+                    return Err(ParseError::SizeOverrun);
+                }
+                self.frame_len = Some(byte as usize);
+                return Ok(None);
+            },
+        };
+
+        if let Err(e) = self.buffer.push(byte) {
+            self.reset();
+            return Err(e);
+        }
+
+        // Expect payload + 1 type + checksum bytes
+        if self.buffer.count == len + 1 + self.buffer.trailer {
+            return self.finish_frame();
+        }
+        Ok(None)
+    }
+
+    fn parse_cobs(&mut self, byte: u8) -> Result<Option<ParserEvent>, ParseError> {
+        if byte == 0 {
+            let idle = self.buffer.count == 0 && self.cobs_remaining == 0;
+            // A frame is only complete if it ended on a block boundary
+            let complete = self.cobs_remaining == 0 && self.buffer.count > self.buffer.trailer;
+            let result = if idle {
+                self.reset();
+                self.has_bad_frame = false;
+                Ok(Some(ParserEvent::FrameStart))
+            } else if complete {
+                self.finish_frame()
+            } else {
+                self.reset();
+                Ok(Some(ParserEvent::Resync))
+            };
+            // The delimiter also starts the next frame
+            self.parsing = true;
+            return result;
+        }
+        if !self.parsing {
+            self.dropped += 1;
+            return Ok(None);
+        }
+
+        let data = if self.cobs_remaining > 0 {
+            self.cobs_remaining -= 1;
+            byte
+        } else {
+            // A code byte starts a new block, which means the previous block
+            // ended in a zero unless it was a full 254 bytes
+            if self.buffer.count == 0 && !self.cobs_zero {
+                self.has_bad_frame = false;
+            }
+            let zero = self.cobs_zero;
+            self.cobs_remaining = byte - 1;
+            self.cobs_zero = byte != 0xff;
+            if !zero {
+                return Ok(None);
+            }
+            0
+        };
+        if let Err(e) = self.buffer.push(data) {
+            self.reset();
+            return Err(e);
+        }
+        Ok(None)
+    }
+
+    /// The received and expected CRC32 of the complete frame in the buffer, if
+    /// it carries one and they differ
+    #[cfg(feature = "crc32")]
+    fn crc32_mismatch(&self) -> Option<(u32, u32)> {
+        if !self.verify_checksum || self.buffer.trailer < 6 {
+            return None;
+        }
+        let n = self.buffer.count;
+        let bytes = &self.buffer.buffer;
+        let found = u32::from_le_bytes([bytes[n - 4], bytes[n - 3], bytes[n - 2], bytes[n - 1]]);
+        let expected = crc32(&bytes[..n - 6]);
+        if found != expected {
+            Some((found, expected))
+        } else {
+            None
+        }
+    }
+
+    /// Verify and decode the complete frame in the buffer, and reset for the
+    /// next one
+    fn finish_frame(&mut self) -> Result<Option<ParserEvent>, ParseError> {
+        let msg_id = self.buffer.msg_id().unwrap();
+        if !self.verify_checksum || self.buffer.checksum() == self.buffer.running_checksum() {
+            #[cfg(feature = "crc32")]
+            if let Some((found, expected)) = self.crc32_mismatch() {
+                if self.retain_bad_frame {
+                    self.bad_frame.clone_from(&self.buffer);
+                    self.has_bad_frame = true;
+                }
+                self.reset();
+                return Err(ParseError::CrcError { id: msg_id, found, expected });
+            }
+            let payload = self.buffer.payload();
+            #[cfg(feature = "alloc")]
+            if let Some(observer) = self.observer.0.as_mut() {
+                observer(msg_id, payload);
+            }
+            self.last_seq = self.buffer.seq();
+            let result = match self.pool.decode(msg_id, payload, self.endian) {
+                #[cfg(feature = "alloc")]
+                _ if self.buffer.is_resized(msg_id, payload) => {
+                    Ok(Message::RawMessage { id: msg_id, payload: payload.into() })
+                },
+                #[cfg(feature = "alloc")]
+                Err(ParseError::UnknownPacketId(_)) if self.lenient => {
+                    Ok(Message::RawMessage { id: msg_id, payload: payload.into() })
+                },
+                result @ Err(ParseError::UnknownPacketId(_)) => result,
+                // Only possible when the frame length is explicit, as
+                // byte-stuffed frames end at the expected size
+                result => match Message::message_size(msg_id, payload) {
+                    Some(expected) if payload.len() > expected => {
+                        Err(ParseError::PayloadTooLong { id: msg_id, expected, actual: payload.len() })
+                    },
+                    _ => result,
+                },
+            };
+            self.reset();
+            result.map(|msg| Some(ParserEvent::Message(msg)))
+        } else {
+            let (found_a, found_b) = self.buffer.checksum();
+            let (exp_a, exp_b) = self.buffer.running_checksum();
+            let found = (found_a as u16) + (found_b as u16) * 256;
+            let expected = (exp_a as u16) + (exp_b as u16) * 256;
+            if self.retain_bad_frame {
+                self.bad_frame.clone_from(&self.buffer);
+                self.has_bad_frame = true;
+            }
+            if self.framing() == FramingMode::LengthPrefixed && self.fast_resync {
+                self.replay_from_sof();
+            } else if self.framing() == FramingMode::ByteStuffed {
+                // The frame may have only looked complete because its payload
+                // is longer than the id allows, so keep collecting in case a
+                // valid checksum turns up later
+                self.overlong = Some(self.buffer.payload().len());
+            } else {
+                self.reset();
+            }
+            Err(ParseError::ChecksumError { id: msg_id, found, expected })
+        }
+    }
+}
+
+/// Decode messages from any source of bytes, yielding each message (or error)
+/// as its frame completes
+#[cfg(feature = "decode")]
+pub fn decode_iter<I>(iter: I) -> impl Iterator<Item = Result<Message, ParseError>>
+where
+    I: IntoIterator<Item = u8>
+{
+    let mut parser = Parser::new();
+    iter.into_iter().filter_map(move |b| parser.parse(b).transpose())
+}
+
+/// Serialize a message and parse it back, for testing code built on this
+/// protocol
+///
+/// Returns the parser's error if the frame doesn't decode, or
+/// `DeserializationError` if the payload is too short for the parser to see
+/// the end of the frame, e.g. a `RawMessage` with a known id.
+#[cfg(all(feature = "encode", feature = "decode", any(test, feature = "test-util")))]
+pub fn roundtrip(msg: &Message) -> Result<Message, ParseError> {
+    let id = msg.id();
+    let payload = msg.payload();
+    let mut parser = Parser::new();
+    for b in serialize_raw(id, &payload) {
+        if let Some(msg) = parser.parse(b)? {
+            return Ok(msg);
+        }
+    }
+    Err(ParseError::DeserializationError {
+        id,
+        expected: Message::message_size(id, &payload).unwrap_or(0),
+        actual: payload.len(),
+    })
+}
+
+/// Decode messages from an async reader, yielding each message (or error) as
+/// its frame completes
+///
+/// Partial frames are carried over between reads. The stream ends when the
+/// reader reaches EOF, or after yielding `ReadError` if a read fails.
+#[cfg(all(feature = "decode", feature = "futures"))]
+pub fn message_stream<R>(r: R) -> impl futures::Stream<Item = Result<Message, ParseError>>
+where
+    R: futures::AsyncRead + Unpin
+{
+    use futures::AsyncReadExt;
+
+    struct State<R> {
+        reader: R,
+        parser: Parser,
+        buf: [u8; 64],
+        pos: usize,
+        len: usize,
+        done: bool,
+    }
+
+    let state = State{reader: r, parser: Parser::new(), buf: [0; 64], pos: 0, len: 0, done: false};
+    futures::stream::unfold(state, |mut st| async move {
+        loop {
+            while st.pos < st.len {
+                let b = st.buf[st.pos];
+                st.pos += 1;
+                if let Some(result) = st.parser.parse(b).transpose() {
+                    return Some((result, st));
+                }
+            }
+            if st.done {
+                return None;
+            }
+            match st.reader.read(&mut st.buf).await {
+                Ok(0) => return None,
+                Ok(n) => {
+                    st.pos = 0;
+                    st.len = n;
+                },
+                Err(_) => {
+                    st.done = true;
+                    return Some((Err(ParseError::ReadError), st));
+                },
+            }
+        }
+    })
+}
+
+/// Feed bytes from the front of `buf` to `parser` until a message completes,
+/// advancing `buf` past the bytes consumed
+///
+/// Bytes after a completed message, or an error, are left in `buf` for the
+/// next call. A partial frame is consumed and held by the parser, so `buf` is
+/// empty when this returns `Ok(None)`.
+#[cfg(all(feature = "bytes", feature = "decode"))]
+pub fn decode_from_buf(buf: &mut bytes::BytesMut, parser: &mut Parser) -> Result<Option<Message>, ParseError> {
+    use bytes::Buf;
+    let mut consumed = 0;
+    let mut result = Ok(None);
+    for b in buf.iter() {
+        consumed += 1;
+        result = parser.parse(*b);
+        if !matches!(result, Ok(None)) {
+            break;
+        }
+    }
+    buf.advance(consumed);
+    result
+}
+
+/// Decode messages from a capture file of length-delimited records, each a
+/// little-endian u16 byte count followed by that many bytes off the wire
+///
+/// Records are fed through one parser in order, so frames may be split across
+/// records or several may share one. A failed read yields `ReadError`, and a
+/// capture ending part way through a record yields `TruncatedFrame`; either
+/// ends the iterator. The end of the capture is otherwise checked with
+/// `Parser::finish`.
+#[cfg(all(feature = "std", feature = "decode"))]
+pub fn replay_reader<R>(r: R) -> impl Iterator<Item = Result<Message, ParseError>>
+where
+    R: std::io::Read
+{
+    struct State<R> {
+        reader: R,
+        parser: Parser,
+        record: Vec<u8>,
+        pos: usize,
+        done: bool,
+    }
+
+    /// Read the next record into `record`, returning false at the end of the
+    /// capture
+    fn read_record<R: std::io::Read>(reader: &mut R, record: &mut Vec<u8>) -> Result<bool, ParseError> {
+        use std::io::ErrorKind;
+        let mut len = [0u8; 2];
+        match reader.read(&mut len[..1]) {
+            Ok(0) => return Ok(false),
+            Ok(_) => (),
+            Err(_) => return Err(ParseError::ReadError),
+        }
+        let to_error = |e: std::io::Error| match e.kind() {
+            ErrorKind::UnexpectedEof => ParseError::TruncatedFrame,
+            _ => ParseError::ReadError,
+        };
+        reader.read_exact(&mut len[1..]).map_err(to_error)?;
+        record.resize(u16::from_le_bytes(len) as usize, 0);
+        reader.read_exact(record).map_err(to_error)?;
+        Ok(true)
+    }
+
+    let mut st = State{reader: r, parser: Parser::new(), record: Vec::new(), pos: 0, done: false};
+    core::iter::from_fn(move || {
+        loop {
+            while st.pos < st.record.len() {
+                let b = st.record[st.pos];
+                st.pos += 1;
+                if let Some(result) = st.parser.parse(b).transpose() {
+                    return Some(result);
+                }
+            }
+            if st.done {
+                return None;
+            }
+            st.pos = 0;
+            match read_record(&mut st.reader, &mut st.record) {
+                Ok(true) => (),
+                Ok(false) => {
+                    st.done = true;
+                    st.record.clear();
+                    // Judge the end of the capture the same way as the end of
+                    // any other stream
+                    if let Err(e) = core::mem::take(&mut st.parser).finish() {
+                        return Some(Err(e));
+                    }
+                },
+                Err(e) => {
+                    st.done = true;
+                    st.record.clear();
+                    return Some(Err(e));
+                },
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+#[cfg(all(feature = "std", not(test)))]
+extern crate std;
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::alloc::vec;
+    use crate::alloc::vec::Vec;
+    use crate::Parser;
+    use crate::Message;
+    use crate::ParseError;
+    fn append_checksum(data: &mut Vec<u8>) {
+        use crate::checksum;
+        let (chk_a, chk_b) = checksum(&data[1..data.len()]);
+        std::println!("Appending {:x} {:x} to packet", chk_a, chk_b);
+        data.append(&mut vec![chk_a, chk_b]);
+    }
+
+    fn parse_message(parser: &mut Parser, data: &[u8]) -> Result<Option<Message>, ParseError> {
+        for b in data {
+            let result = parser.parse(*b)?;
+            if let Some(msg) = result {
+                return Ok(Some(msg));
+            }
+        }
+        Ok(None)
+    }
+
+
+    #[test]
+    fn test_bulk_capacitance_parse() {
+        use crate::*;
+        let mut bytes = vec![0x7e, BULK_CAPACITANCE_ID, 0, 2, 4, 0, 5, 0];
+        append_checksum(&mut bytes);
+        let mut rxmsg = None;
+        let mut parser = Parser::new();
+        let result = parse_message(&mut parser, &bytes);
+        if result.is_err() {
+            panic!("Error while parsing: {}", result.err().unwrap());
+        }
+        let result = result.unwrap();
+        if let Some(msg) = result {
+            use Message::*;
+            match msg {
+                BulkCapacitanceMsg(msg) => rxmsg = Some(msg),
+                _ => panic!("Got unexpected  messaged: {:?}", msg),
+            }
+        }
+        assert!(rxmsg.is_some());
+        let rxmsg = rxmsg.unwrap();
+        assert_eq!(rxmsg.values.len(), 2);
+        assert_eq!(rxmsg.values[0], 4);
+        assert_eq!(rxmsg.values[1], 5);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_bulk_capacitance_empty_roundtrip() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct{ start_index: 9, values: vec![] };
+        let bytes = serialize_msg(&msg);
+        assert_eq!(bytes.len(), 6);
+        // Complete exactly once the two header bytes and checksum arrive
+        let mut parser = Parser::new();
+        for b in &bytes[..bytes.len() - 1] {
+            assert_eq!(parser.parse(*b).unwrap(), None);
+        }
+        let result = parser.parse(bytes[bytes.len() - 1]).unwrap();
+        assert_eq!(result, Some(Message::BulkCapacitanceMsg(msg)));
+        assert!(parser.in_progress().is_none());
+    }
+
+    #[test]
+    fn test_active_capacitance_parse() {
+        use crate::*;
+        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        append_checksum(&mut bytes);
+        let mut parser = Parser::new();
+        let result = parse_message(&mut parser, &bytes);
+        if result.is_err() {
+            panic!("Error while parsing: {}", result.err().unwrap());
+        }
+        let result = result.unwrap();
+        if let Some(msg) = result {
+            use Message::*;
+            match msg {
+                ActiveCapacitanceMsg(msg) => {
+                    assert_eq!(msg.baseline, 0x302);
+                    assert_eq!(msg.measurement, 0x504);
+                },
+                _ => panic!("Invalid message type found"),
+            }
+        } else {
+            panic!("No message parsed");
+        }
+    }
+
+    #[test]
+    fn test_electrode_enable_roundtrip() {
+        use crate::*;
+        let values: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0x7d, 0x7e];
+        let tx_msg = ElectrodeEnableStruct{ values };
+        let payload: Vec<u8> = tx_msg.payload();
+        let tx_bytes = serialize_raw(ELECTRODE_ENABLE_ID, &payload);
+        let mut parser = Parser::new();
+        let result = parse_message(&mut parser, &tx_bytes);
+        if result.is_err() {
+            panic!("Error while parsing: {}", result.err().unwrap());
+        }
+        let rx_msg = result.unwrap();
+        assert!(rx_msg.is_some());
+        let rx_msg = rx_msg.unwrap();
+        if let Message::ElectrodeEnableMsg(msg) = rx_msg {
+            assert_eq!(msg.values, values);
+        } else {
+            panic!("Did not parse expected message");
+        }
+    }
+
+    #[test]
+    fn test_parse_legacy_command_ack() {
+        use crate::*;
+        let version = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let mut bytes = serialize_raw(COMMAND_ACK_ID, &[MOVE_STEPPER_ID]);
+        bytes.extend(serialize_msg(&CommandAckStruct{ acked_id: REBOOT_ID, status: 4 }));
+        bytes.extend(serialize_msg(&version));
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        parser.feed(&bytes, &mut out).unwrap();
+        assert_eq!(out, vec![
+            Message::CommandAckMsg(CommandAckStruct{ acked_id: MOVE_STEPPER_ID, status: 0 }),
+            Message::CommandAckMsg(CommandAckStruct{ acked_id: REBOOT_ID, status: 4 }),
+            Message::VersionMsg(version),
+        ]);
+    }
+
+    #[test]
+    fn test_escaped_checksum_roundtrip() {
+        use crate::*;
+        // Chosen so that both checksum bytes need escaping
+        let tx_msg = CommandAckStruct{ acked_id: 0xf9, status: 0x80 };
+        assert_eq!(checksum(&[COMMAND_ACK_ID, 0xf9, 0x80]), (0x7d, 0x7e));
+        let tx_bytes = serialize_msg(&tx_msg);
+        assert_eq!(&tx_bytes[tx_bytes.len() - 4..], &[0x7d, 0x5d, 0x7d, 0x5e]);
+        let mut parser = Parser::new();
+        let rx_msg = parse_message(&mut parser, &tx_bytes).unwrap();
+        assert_eq!(rx_msg, Some(Message::CommandAckMsg(tx_msg)));
+        assert!(!parser.is_parsing());
+    }
+
+    #[test]
+    fn test_reserialize_move_stepper() {
+        use crate::*;
+        // Negative steps (0xff7e) with an escaped byte, to exercise the i16 cast
+        let tx_bytes = serialize_msg(&MoveStepperStruct{ steps: -130, period: 0x7e7d });
+        let mut parser = Parser::new();
+        let rx_msg = parse_message(&mut parser, &tx_bytes).unwrap().unwrap();
+        assert_eq!(rx_msg.serialize(), tx_bytes);
+    }
+
+    #[test]
+    fn test_verify_frames() {
+        use crate::*;
+        let mut buf = serialize_raw(VERSION_ID, &[1, 0x7e, 3]);
+        let mut bad = serialize_raw(CONFIG_GET_ID, &[4, 5]);
+        let last = bad.len() - 1;
+        bad[last] ^= 0x01;
+        buf.extend(bad);
+        buf.extend(serialize_raw(ACTIVE_CAPACITANCE_ID, &[0x7d, 1, 2, 3]));
+        let results: Vec<_> = verify_frames(&buf).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &(VERSION_ID, vec![1, 0x7e, 3]));
+        assert!(matches!(results[1], Err(ParseError::ChecksumError { id: CONFIG_GET_ID, .. })));
+        assert_eq!(results[2].as_ref().unwrap(), &(ACTIVE_CAPACITANCE_ID, vec![0x7d, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_checksum_modes() {
+        use crate::*;
+        let payload = [0xff, 0x80, 0x90];
+        assert_eq!(checksum_with(ChecksumMode::Wrapping, &[CONFIG_GET_ID, 0xff, 0x80]), (0x87, 0x96));
+        assert_eq!(checksum_with(ChecksumMode::Fletcher16, &[CONFIG_GET_ID, 0xff, 0x80]), (0x88, 0x98));
+        for mode in [ChecksumMode::Wrapping, ChecksumMode::Fletcher16] {
+            for framing in [FramingMode::ByteStuffed, FramingMode::LengthPrefixed, FramingMode::Cobs] {
+                let bytes = Encoder::new_with_mode(framing).with_checksum_mode(mode).serialize_raw(VERSION_ID, &payload);
+                let mut parser = Parser::new_with_mode(framing).with_checksum_mode(mode);
+                let msg = parse_message(&mut parser, &bytes).unwrap();
+                assert_eq!(msg, Some(Message::VersionMsg(VersionStruct{ major: 0xff, minor: 0x80, patch: 0x90 })));
+
+                let other = if mode == ChecksumMode::Wrapping { ChecksumMode::Fletcher16 } else { ChecksumMode::Wrapping };
+                let mut parser = Parser::new_with_mode(framing).with_checksum_mode(other);
+                assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::ChecksumError { .. })));
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum_error_reports_id() {
+        use crate::*;
+        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        append_checksum(&mut bytes);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        let mut parser = Parser::new();
+        match parse_message(&mut parser, &bytes) {
+            Err(ParseError::ChecksumError { id, found, expected }) => {
+                assert_eq!(id, ACTIVE_CAPACITANCE_ID);
+                assert_eq!(found ^ expected, 0x100);
+            },
+            other => panic!("Expected checksum error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_event_resync() {
+        use crate::*;
+        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3];
+        let mut frame = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        append_checksum(&mut frame);
+        bytes.append(&mut frame);
+        let mut parser = Parser::new();
+        let events: Vec<ParserEvent> = bytes.iter()
+            .filter_map(|b| parser.parse_event(*b).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], ParserEvent::FrameStart));
+        assert!(matches!(events[1], ParserEvent::Resync));
+        assert!(matches!(events[2], ParserEvent::Message(Message::ActiveCapacitanceMsg(_))));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_serialized_max_len_bound() {
+        use crate::*;
+        let values: [u8; 16] = [0x7e; 16];
+        let msg = ElectrodeEnableStruct{ values };
+        let bytes = serialize_msg(&msg);
+        assert!(msg.serialized_max_len() >= bytes.len());
+        assert!(Message::ElectrodeEnableMsg(msg).serialized_max_len() >= bytes.len());
+
+        let msg = BulkCapacitanceStruct{ start_index: 0x7e, values: vec![0x7e7d, 0x7d7e] };
+        let bytes = serialize_msg(&msg);
+        assert!(msg.serialized_max_len() >= bytes.len());
+        assert_eq!(msg.serialized_max_len(), max_framed_len(msg.payload().len()));
+    }
+
+    #[test]
+    fn test_truncated_command_ack_frame() {
+        use crate::*;
+        // An ack frame with its one payload byte missing
+        let mut bytes = vec![0x7e, COMMAND_ACK_ID];
+        append_checksum(&mut bytes);
+        let mut frame = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        append_checksum(&mut frame);
+        bytes.append(&mut frame);
+
+        let mut parser = Parser::new();
+        let mut events = Vec::new();
+        for b in &bytes {
+            if let Some(event) = parser.parse_event(*b).unwrap() {
+                events.push(event);
+            }
+        }
+        // The truncated frame is dropped at the next start byte, and the
+        // following frame is unaffected
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[1], ParserEvent::Resync));
+        match &events[2] {
+            ParserEvent::Message(Message::ActiveCapacitanceMsg(msg)) => {
+                assert_eq!(msg.baseline, 0x302);
+                assert_eq!(msg.measurement, 0x504);
+            },
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        // parse_verbose reports the truncation at the same byte, which parse
+        // passes over
+        let mut parser = Parser::new();
+        let results: Vec<_> = bytes.iter().map(|b| parser.parse_verbose(*b)).collect();
+        assert!(matches!(results[4], (Ok(None), Some(ResetReason::NewFrame))));
+        assert!(matches!(results.last(), Some((Ok(Some(Message::ActiveCapacitanceMsg(_))), _))));
+        let mut parser = Parser::new();
+        assert!(bytes.iter().all(|b| parser.parse(*b).is_ok()));
+    }
+
+    #[test]
+    fn test_in_progress() {
+        use crate::*;
+        let mut parser = Parser::new();
+        assert!(parser.in_progress().is_none());
+        for b in &[0x7e, BULK_CAPACITANCE_ID, 0, 2, 4] {
+            assert!(parser.parse(*b).unwrap().is_none());
+        }
+        let (id, partial) = parser.in_progress().unwrap();
+        assert_eq!(id, BULK_CAPACITANCE_ID);
+        assert_eq!(partial, &[0, 2, 4]);
+    }
+
+    #[test]
+    fn test_is_parsing() {
+        use crate::*;
+        let bytes = serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        let mut parser = Parser::new();
+        assert!(!parser.is_parsing());
+        for b in &bytes[..3] {
+            parser.parse(*b).unwrap();
+        }
+        assert!(parser.is_parsing());
+        for b in &bytes[3..] {
+            parser.parse(*b).unwrap();
+        }
+        assert!(!parser.is_parsing());
+    }
+
+    #[test]
+    fn test_bytes_outside_frame_dropped() {
+        use crate::*;
+        let msg = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        // A stray byte that looks like a message id must not start a frame
+        let mut bytes = vec![ACTIVE_CAPACITANCE_ID];
+        bytes.extend(serialize_msg(&msg));
+        let mut parser = Parser::new();
+        assert!(parser.parse(bytes[0]).unwrap().is_none());
+        assert!(!parser.is_parsing());
+        assert!(parser.in_progress().is_none());
+        assert_eq!(parser.bytes_dropped(), 1);
+        let rx = parse_message(&mut parser, &bytes[1..]).unwrap();
+        assert_eq!(rx, Some(Message::ActiveCapacitanceMsg(msg)));
+        assert_eq!(parser.bytes_dropped(), 1);
+    }
+
+    #[test]
+    fn test_timeout_expires_partial_frame() {
+        use crate::*;
+        let bytes = serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        let mut parser = Parser::new().with_timeout_ticks(3);
+        // Idle ticks outside a frame do nothing
+        for _ in 0..5 {
+            parser.tick().unwrap();
+        }
+        for b in &bytes[..3] {
+            parser.parse(*b).unwrap();
+        }
+        parser.tick().unwrap();
+        parser.tick().unwrap();
+        // A new byte restarts the timer
+        parser.parse(bytes[3]).unwrap();
+        parser.tick().unwrap();
+        parser.tick().unwrap();
+        assert!(matches!(parser.tick(), Err(ParseError::Timeout)));
+        assert!(!parser.is_parsing());
+        // The rest of the stalled frame is discarded, and the next one parses
+        assert!(parse_message(&mut parser, &bytes[4..]).unwrap().is_none());
+        let rx = parse_message(&mut parser, &bytes).unwrap();
+        assert!(matches!(rx, Some(Message::VersionMsg(_))));
+    }
+
+    #[test]
+    fn test_sequence_roundtrip() {
+        use crate::*;
+        let msg = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let mut encoder = Encoder::new().with_sequence(true);
+        let mut bytes = encoder.serialize_msg(&msg);
+        bytes.extend(encoder.serialize_msg(&msg));
+        let mut parser = Parser::new().with_sequence(true);
+        let mut decoded = Vec::new();
+        for b in &bytes {
+            if let Some(rx) = parser.parse_seq(*b).unwrap() {
+                decoded.push(rx);
+            }
+        }
+        let expected = Message::VersionMsg(msg);
+        assert_eq!(decoded, vec![(Some(0), expected.clone()), (Some(1), expected)]);
+
+        // The checksum covers the sequence byte
+        let mut bytes = encoder.serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        assert_eq!(bytes[2], 2);
+        bytes[2] = 3;
+        assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::ChecksumError { .. })));
+    }
+
+    #[test]
+    fn test_sequence_length_prefixed() {
+        use crate::*;
+        let mut encoder = Encoder::new_with_mode(FramingMode::LengthPrefixed).with_sequence(true);
+        encoder.serialize_raw(ELECTRODE_ENABLE_ID, &[0; 16]);
+        let bytes = encoder.serialize_raw(ELECTRODE_ENABLE_ID, &[0x7e; 16]);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed).with_sequence(true);
+        let mut decoded = None;
+        for b in &bytes {
+            if let Some(rx) = parser.parse_seq(*b).unwrap() {
+                decoded = Some(rx);
+            }
+        }
+        let expected = Message::ElectrodeEnableMsg(ElectrodeEnableStruct{ values: [0x7e; 16] });
+        assert_eq!(decoded, Some((Some(1), expected)));
+    }
+
+    #[test]
+    fn test_checksum_verification_disabled() {
+        use crate::*;
+        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        append_checksum(&mut bytes);
+        bytes[6] ^= 0x01;
+        let mut parser = Parser::new();
+        assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::ChecksumError { .. })));
+        let mut parser = Parser::new().with_verify_checksum(false);
+        let rx = parse_message(&mut parser, &bytes).unwrap();
+        assert!(matches!(rx, Some(Message::ActiveCapacitanceMsg(_))));
+    }
+
+    #[test]
+    fn test_big_endian_parse() {
+        use crate::*;
+        let msg = MoveStepperStruct{ steps: -300, period: 1000 };
+        let bytes = serialize_raw(MOVE_STEPPER_ID, &msg.payload_endian(Endian::Big));
+        let mut parser = Parser::new().with_endian(Endian::Big);
+        let rx = parse_message(&mut parser, &bytes).unwrap();
+        assert_eq!(rx, Some(Message::MoveStepperMsg(msg)));
+    }
+
+    #[test]
+    fn test_checksum_scatter_gather() {
+        use crate::*;
+        let header = [ACTIVE_CAPACITANCE_ID];
+        let payload = [2, 3, 4, 5];
+        let contiguous = checksum(&[ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5]);
+        let mut chk = Checksum::default();
+        chk.extend(header.iter().chain(payload.iter()));
+        assert_eq!(chk.get(), contiguous);
+        let chk: Checksum = header.iter().chain(payload.iter()).copied().collect();
+        assert_eq!(chk.get(), contiguous);
+        let mut chk = Checksum::default();
+        chk.add_bytes(&header);
+        chk.add_bytes(&payload);
+        assert_eq!(chk.get(), contiguous);
+    }
+
+    #[test]
+    fn test_serialize_all() {
+        use crate::*;
+        let msgs = vec![
+            Message::VersionMsg(VersionStruct{ major: 1, minor: 2, patch: 3 }),
+            Message::MoveStepperMsg(MoveStepperStruct{ steps: -5, period: 0x7e7d }),
+        ];
+        let bytes = serialize_all(&msgs);
+        let mut decoded = Vec::new();
+        Parser::new().feed(&bytes, &mut decoded).unwrap();
+        assert_eq!(decoded, msgs);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_custom_frame_config() {
+        use crate::*;
+        let config = FrameConfig{ sof: 0xc0, escape: 0xdb, xor: 0x01 };
+        let msg = BulkCapacitanceStruct{ start_index: 0x7e, values: vec![0xc0db, 0x7e7d] };
+        let bytes = serialize_raw_with_config(&config, BULK_CAPACITANCE_ID, &msg.payload());
+        assert_eq!(bytes.iter().filter(|b| **b == 0xc0).count(), 1);
+        // 0x7e and 0x7d are plain data with this config
+        assert_eq!(bytes.len(), msg.payload().len() + 4 + 2);
+        let mut parser = Parser::new_with_config(config);
+        let rx = parse_message(&mut parser, &bytes).unwrap();
+        assert_eq!(rx, Some(Message::BulkCapacitanceMsg(msg)));
+    }
+
+    #[test]
+    fn test_cobs_roundtrip() {
+        use crate::*;
+        let mut values = [0u8; 16];
+        values[3] = 0x7e;
+        values[15] = 1;
+        let tx_msg = ElectrodeEnableStruct{ values };
+        let tx_bytes = serialize_raw_framed(FramingMode::Cobs, ELECTRODE_ENABLE_ID, &tx_msg.payload());
+        // Zeros only appear as delimiters
+        assert_eq!(tx_bytes[0], 0);
+        assert_eq!(tx_bytes[tx_bytes.len() - 1], 0);
+        assert!(tx_bytes[1..tx_bytes.len() - 1].iter().all(|b| *b != 0));
+        let mut parser = Parser::new_with_mode(FramingMode::Cobs);
+        let rx = parse_message(&mut parser, &tx_bytes).unwrap();
+        assert_eq!(rx, Some(Message::ElectrodeEnableMsg(tx_msg)));
+    }
+
+    #[test]
+    fn test_cobs_trailing_delimiters_only() {
+        use crate::*;
+        let msgs = vec![
+            Message::VersionMsg(VersionStruct{ major: 0, minor: 0, patch: 0 }),
+            Message::ActiveCapacitanceMsg(ActiveCapacitanceStruct{ baseline: 0x100, measurement: 0 }),
+        ];
+        let mut bytes = Vec::new();
+        for msg in &msgs {
+            let frame = serialize_raw_framed(FramingMode::Cobs, msg.id(), &msg.payload());
+            bytes.extend_from_slice(&frame[1..]);
+        }
+        let mut parser = Parser::new_with_mode(FramingMode::Cobs);
+        let mut decoded = Vec::new();
+        parser.feed(&[0], &mut decoded).unwrap();
+        parser.feed(&bytes, &mut decoded).unwrap();
+        assert_eq!(decoded, msgs);
+
+        // A frame cut off mid-block is discarded at the next delimiter
+        let frame = serialize_raw_framed(FramingMode::Cobs, msgs[0].id(), &msgs[0].payload());
+        let mut events = Vec::new();
+        for b in frame[..3].iter().chain(frame.iter()) {
+            if let Some(event) = parser.parse_event(*b).unwrap() {
+                events.push(event);
+            }
+        }
+        assert!(matches!(events[..], [ParserEvent::FrameStart, ParserEvent::Resync, ParserEvent::Message(_)]));
+    }
+
+    #[test]
+    fn test_working_buffer_short_counts() {
+        use crate::*;
+        let mut buffer = WorkingBuffer::new();
+        for count in 0..=3 {
+            assert_eq!(buffer.count, count);
+            assert_eq!(buffer.payload(), &[] as &[u8]);
+            assert_eq!(buffer.partial_payload().len(), count.saturating_sub(1));
+            if count < 3 {
+                assert_eq!(buffer.checksum(), (0, 0));
+                assert_eq!(buffer.calc_checksum(), (0, 0));
+            }
+            assert!(!buffer.is_complete() || count == 3);
+            buffer.push(VERSION_ID).unwrap();
+        }
+        // With sequencing the header is one byte longer
+        let mut buffer = WorkingBuffer::new();
+        buffer.header = 2;
+        for _ in 0..3 {
+            buffer.push(1).unwrap();
+            assert_eq!(buffer.payload(), &[] as &[u8]);
+            assert_eq!(buffer.calc_checksum(), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_working_buffer_as_slice() {
+        use crate::*;
+        let mut buffer = WorkingBuffer::new();
+        assert_eq!(buffer.as_slice(), &[] as &[u8]);
+        assert_eq!(format!("{}", buffer), "");
+        for b in &[VERSION_ID, 0x7e, 0x0a] {
+            buffer.push(*b).unwrap();
+        }
+        assert_eq!(buffer.as_slice(), &[VERSION_ID, 0x7e, 0x0a]);
+        assert_eq!(format!("{}", buffer), "06 7e 0a");
+    }
+
+    #[test]
+    fn test_move_stepper_frame_completes() {
+        use crate::*;
+        let payload = MoveStepperStruct{ steps: -100, period: 250 }.payload();
+        assert_eq!(Message::message_size(MOVE_STEPPER_ID, &payload), Some(4));
+        let mut frame = vec![MOVE_STEPPER_ID];
+        frame.extend(&payload);
+        let (chk_a, chk_b) = checksum(&frame);
+        frame.extend(&[chk_a, chk_b]);
+        let mut buffer = WorkingBuffer::new();
+        for (i, b) in frame.iter().enumerate() {
+            assert!(!buffer.is_complete());
+            buffer.push(*b).unwrap();
+            // Complete once the id, payload and both checksum bytes are in
+            assert_eq!(buffer.is_complete(), i + 1 == payload.len() + 3);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_working_buffer_payload_vec() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct{ start_index: 3, values: vec![0x7e7d, 1, 2] };
+        let mut buffer = WorkingBuffer::new();
+        buffer.push(BULK_CAPACITANCE_ID).unwrap();
+        for b in msg.payload() {
+            buffer.push(b).unwrap();
+        }
+        buffer.push(0).unwrap();
+        buffer.push(0).unwrap();
+        let payload = buffer.payload_vec();
+        assert_eq!(payload, buffer.payload());
+        assert_eq!(payload, msg.payload());
+        buffer.reset();
+        assert_eq!(payload, msg.payload());
+    }
+
+    #[test]
+    fn test_running_checksum() {
+        use crate::*;
+        let bytes = [VERSION_ID, 1, 2, 3, 0x7e, 0x00, 0xff];
+        let mut buffer = WorkingBuffer::new();
+        for (i, b) in bytes.iter().enumerate() {
+            buffer.push(*b).unwrap();
+            if i >= 2 {
+                assert_eq!(buffer.running_checksum(), buffer.calc_checksum());
+            }
+        }
+        buffer.reset();
+        assert_eq!(buffer.running_checksum(), (0, 0));
+    }
+
+    #[test]
+    fn test_overlong_payload() {
+        use crate::*;
+        let mut bytes = serialize_raw(ELECTRODE_ENABLE_ID, &[1; 18]);
+        // The end of the frame is only known once the next one starts
+        bytes.push(0x7e);
+        let mut parser = Parser::new();
+        let mut errors = Vec::new();
+        for b in &bytes {
+            if let Err(e) = parser.parse(*b) {
+                errors.push(e);
+            }
+        }
+        // The frame looks complete after 16 bytes, so the checksum fails
+        // first, but the real end of the frame is then recognized
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], ParseError::ChecksumError { .. }));
+        match errors[1] {
+            ParseError::PayloadTooLong { id, expected, actual } => {
+                assert_eq!(id, ELECTRODE_ENABLE_ID);
+                assert_eq!(expected, 16);
+                assert_eq!(actual, 18);
+            },
+            ref other => panic!("Expected PayloadTooLong, got {:?}", other),
+        }
+        let rx = parse_message(&mut parser, &serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 }));
+        assert!(matches!(rx, Ok(Some(Message::VersionMsg(_)))));
+
+        // A checksum which only matches part way through a longer frame isn't
+        // taken as the end of it
+        let mut bytes = serialize_raw(ELECTRODE_ENABLE_ID, &[1; 17]);
+        bytes.extend(&[0, 0, 0x7e]);
+        let mut parser = Parser::new();
+        let errors: Vec<_> = bytes.iter().filter_map(|b| parser.parse(*b).err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], ParseError::ChecksumError { .. }));
+
+        // With an explicit length it's reported directly
+        let bytes = serialize_raw_framed(FramingMode::LengthPrefixed, ELECTRODE_ENABLE_ID, &[1; 18]);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        assert!(matches!(
+            parse_message(&mut parser, &bytes),
+            Err(ParseError::PayloadTooLong { id: ELECTRODE_ENABLE_ID, expected: 16, actual: 18 })
+        ));
+    }
+
+    #[test]
+    fn test_drain() {
+        use crate::*;
+        let mut parser = Parser::new();
+        assert!(parser.drain().is_none());
+        for b in &[0x7e, BULK_CAPACITANCE_ID, 0x7d, 0x5e, 2] {
+            parser.parse(*b).unwrap();
+        }
+        assert_eq!(parser.drain(), Some(&[BULK_CAPACITANCE_ID, 0x7e, 2][..]));
+        assert!(!parser.is_parsing());
+        assert!(parser.in_progress().is_none());
+        assert!(parser.drain().is_none());
+    }
+
+    #[test]
+    fn test_framed_len() {
+        use crate::*;
+        let payload = [1, 0x7d, 2, 0x7e];
+        assert_eq!(framed_len(VERSION_ID, &payload), serialize_raw(VERSION_ID, &payload).len());
+        assert_eq!(framed_len(VERSION_ID, &payload), 1 + 1 + 6 + 2);
+        // Escaped id and checksum bytes count too
+        for b in 0..=255u8 {
+            assert_eq!(framed_len(0x7e, &[b]), serialize_raw(0x7e, &[b]).len());
+        }
+    }
+
+    #[test]
+    fn test_finish() {
+        use crate::*;
+        let bytes = serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        assert!(Parser::new().finish().is_ok());
+        let mut parser = Parser::new();
+        parse_message(&mut parser, &bytes).unwrap().unwrap();
+        assert!(parser.finish().is_ok());
+        let mut parser = Parser::new();
+        assert_eq!(parse_message(&mut parser, &bytes[..3]).unwrap(), None);
+        assert!(matches!(parser.finish(), Err(ParseError::TruncatedFrame)));
+
+        let bytes = serialize_raw_framed(FramingMode::LengthPrefixed, VERSION_ID, &[1, 2, 3]);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        assert_eq!(parse_message(&mut parser, &bytes[..2]).unwrap(), None);
+        assert!(matches!(parser.finish(), Err(ParseError::TruncatedFrame)));
+    }
+
+    #[test]
+    fn test_escape_roundtrip() {
+        use crate::*;
+        let data = [1, 0x7d, 0x7e, 2, 0x7e, 0x7d];
+        let mut escaped = Vec::new();
+        escape_into(&data, &mut escaped);
+        assert_eq!(escaped, vec![1, 0x7d, 0x5d, 0x7d, 0x5e, 2, 0x7d, 0x5e, 0x7d, 0x5d]);
+        assert_eq!(unescape(&escaped).unwrap(), data);
+        assert!(matches!(unescape(&[1, 0x7d]), Err(ParseError::DanglingEscape)));
+    }
+
+    #[test]
+    fn test_frame_macro() {
+        use crate::*;
+        const FRAME: [u8; 9] = frame!(ACTIVE_CAPACITANCE_ID, [2, 3, 0x7e, 5]);
+        assert_eq!(&FRAME[..], &serialize_raw(ACTIVE_CAPACITANCE_ID, &[2, 3, 0x7e, 5])[..]);
+        let mut parser = Parser::new();
+        let rx = parse_message(&mut parser, &FRAME).unwrap();
+        let expected = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x57e };
+        assert_eq!(rx, Some(Message::ActiveCapacitanceMsg(expected)));
+    }
+
+    #[test]
+    fn test_leading_noise() {
+        use crate::*;
+        let mut bytes = vec![0xaa, 0xbb, 0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5];
+        let (chk_a, chk_b) = checksum(&bytes[3..]);
+        bytes.extend_from_slice(&[chk_a, chk_b]);
+        let mut parser = Parser::new();
+        let rx = parse_message(&mut parser, &bytes).unwrap();
+        let expected = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        assert_eq!(rx, Some(Message::ActiveCapacitanceMsg(expected)));
+        assert_eq!(parser.bytes_dropped(), 2);
+    }
+
+    #[test]
+    fn test_garbage_resembling_frame_before_sof() {
+        use crate::*;
+        // A complete, valid frame body with no start byte must not decode
+        let bogus = serialize_msg(&VersionStruct{ major: 9, minor: 9, patch: 9 });
+        let real = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let mut bytes = bogus[1..].to_vec();
+        bytes.extend(serialize_msg(&real));
+        let mut decoded = Vec::new();
+        let mut parser = Parser::new();
+        parser.feed(&bytes, &mut decoded).unwrap();
+        assert_eq!(decoded, vec![Message::VersionMsg(real)]);
+        assert_eq!(parser.bytes_dropped(), bogus.len() - 1);
+    }
+
+    #[test]
+    fn test_parse_verbose_reset_reasons() {
+        use crate::*;
+        let mut parser = Parser::new();
+        let mut reasons = Vec::new();
+        let frame = serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        let mut bad = frame.clone();
+        bad[3] ^= 1;
+        let mut bytes = frame[..3].to_vec();
+        bytes.extend(&frame);
+        bytes.extend(&bad);
+        bytes.extend(&[0x7e, BULK_CAPACITANCE_ID, 0, 255]);
+        bytes.extend(&[1; 126]);
+        for b in &bytes {
+            if let (_, Some(reason)) = parser.parse_verbose(*b) {
+                reasons.push(reason);
+            }
+        }
+        assert_eq!(reasons, vec![
+            ResetReason::NewFrame,
+            ResetReason::Completed,
+            ResetReason::ChecksumFailed,
+            ResetReason::Overrun,
+        ]);
+    }
+
+    #[test]
+    fn test_parse_verbose_matches_parse() {
+        use crate::*;
+        let frame = serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 });
+        let mut bad = frame.clone();
+        bad[3] ^= 1;
+        let mut overlong = serialize_raw(ELECTRODE_ENABLE_ID, &[1; 18]);
+        overlong.push(0x7e);
+        let mut overrun = vec![0x7e, BULK_CAPACITANCE_ID, 0, 255];
+        overrun.extend(&[1; 126]);
+        let cases = [
+            (frame[..3].iter().chain(&frame).copied().collect::<Vec<_>>(), ResetReason::NewFrame),
+            (frame.clone(), ResetReason::Completed),
+            (bad, ResetReason::ChecksumFailed),
+            (overrun, ResetReason::Overrun),
+            (overlong, ResetReason::PayloadTooLong),
+            (serialize_raw(REBOOT_ID, &[0; 4]), ResetReason::Completed),
+        ];
+        for (bytes, expected) in cases.iter() {
+            let mut parser = Parser::new();
+            let mut verbose = Parser::new();
+            let mut reasons = Vec::new();
+            for b in bytes {
+                let (result, reason) = verbose.parse_verbose(*b);
+                assert_eq!(format!("{:?}", result), format!("{:?}", parser.parse(*b)));
+                reasons.extend(reason);
+            }
+            assert!(reasons.contains(expected), "{:?} not in {:?}", expected, reasons);
+        }
+    }
+
+    #[test]
+    fn test_fast_resync_after_truncated_frame() {
+        use crate::*;
+        let first = serialize_raw_framed(FramingMode::LengthPrefixed, ACTIVE_CAPACITANCE_ID, &[2, 3, 4, 5]);
+        let msg = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let second = serialize_raw_framed(FramingMode::LengthPrefixed, VERSION_ID, &msg.payload());
+        // The first frame loses a payload byte, so it swallows the start of the second
+        let mut bytes = first.clone();
+        bytes.remove(4);
+        bytes.extend(&second);
+
+        let mut decoded = Vec::new();
+        let mut errors = 0;
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        for b in &bytes {
+            match parser.parse(*b) {
+                Ok(Some(rx)) => decoded.push(rx),
+                Ok(None) => (),
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(errors, 1);
+        assert!(decoded.is_empty());
+
+        decoded.clear();
+        errors = 0;
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed).with_fast_resync(true);
+        for b in &bytes {
+            match parser.parse(*b) {
+                Ok(Some(rx)) => decoded.push(rx),
+                Ok(None) => (),
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(errors, 1);
+        assert_eq!(decoded, vec![Message::VersionMsg(msg.clone())]);
+
+        // A corrupt length which swallows the whole second frame recovers it
+        // on the failing byte, so it isn't lost if the input ends there
+        let mut bytes = first.clone();
+        bytes[1] += second.len() as u8;
+        bytes.extend(&second);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed).with_fast_resync(true);
+        let mut out = Vec::new();
+        assert!(parser.feed(&bytes, &mut out).is_err());
+        assert_eq!(out, vec![Message::VersionMsg(msg.clone())]);
+        assert!(parser.take_recovered().is_none());
+
+        // Byte-stuffed frames resync on the next start byte without help, even
+        // when the failed frame ends with an escaped one
+        let mut bytes = serialize_raw(CONFIG_GET_ID, &[1, 0x7e]);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        bytes.extend(serialize_msg(&msg));
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        assert!(parser.feed(&bytes, &mut out).is_err());
+        parser.feed(&bytes[last + 1..], &mut out).unwrap();
+        assert_eq!(out, vec![Message::VersionMsg(msg.clone())]);
+
+        // A frame with just a bad checksum doesn't disturb the next one
+        let mut bytes = first.clone();
+        bytes[first.len() - 1] ^= 1;
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed).with_fast_resync(true);
+        assert!(parse_message(&mut parser, &bytes).is_err());
+        let rx = parse_message(&mut parser, &second).unwrap();
+        assert_eq!(rx, Some(Message::VersionMsg(msg)));
+    }
+
+    #[test]
+    fn test_serialize_external_checksum() {
+        use crate::*;
+        let payload = [0x7e, 1, 0x7d, 2];
+        let (mut frame, checksum_pos) = serialize_raw_no_checksum(BULK_CAPACITANCE_ID, &payload);
+        assert_eq!(checksum_pos, frame.len());
+        let mut chk = Checksum::default();
+        chk.add_byte(BULK_CAPACITANCE_ID);
+        chk.add_bytes(&payload);
+        finalize_checksum(&mut frame, chk.get());
+        assert_eq!(frame, serialize_raw(BULK_CAPACITANCE_ID, &payload));
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_poll_read() {
+        use crate::*;
+        struct MockSerial(std::collections::VecDeque<u8>);
+        impl embedded_hal::serial::Read<u8> for MockSerial {
+            type Error = ();
+            fn read(&mut self) -> nb::Result<u8, ()> {
+                self.0.pop_front().ok_or(nb::Error::WouldBlock)
+            }
+        }
+        let msg = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let mut bytes = serialize_msg(&msg);
+        bytes.extend(&[0x7e, VERSION_ID]);
+        let mut serial = MockSerial(bytes.into_iter().collect());
+        let mut parser = Parser::new();
+        assert_eq!(parser.poll_read(&mut serial).unwrap(), Some(Message::VersionMsg(msg)));
+        assert_eq!(parser.poll_read(&mut serial).unwrap(), None);
+        assert!(matches!(parser.poll_read(&mut serial), Err(nb::Error::WouldBlock)));
+        assert!(parser.is_parsing());
+    }
+
+    #[test]
+    fn test_roundtrip_helper() {
+        use crate::*;
+        let msg: Message = CommandAckStruct{ acked_id: MOVE_STEPPER_ID, status: 1 }.into();
+        assert_eq!(roundtrip(&msg).unwrap(), msg);
+        let raw = Message::RawMessage{ id: VERSION_ID, payload: vec![1, 2] };
+        assert!(matches!(
+            roundtrip(&raw),
+            Err(ParseError::DeserializationError{ id: VERSION_ID, expected: 3, actual: 2 })
+        ));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_message_stream() {
+        use crate::*;
+        use futures::{AsyncReadExt, StreamExt};
+        let first = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let second = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        let mut bytes = serialize_msg(&first);
+        bytes.extend(serialize_msg(&second));
+        // Split the second frame across two reads
+        let split = bytes.len() - 3;
+        let reader = futures::io::Cursor::new(bytes[..split].to_vec())
+            .chain(futures::io::Cursor::new(bytes[split..].to_vec()));
+        let messages: Vec<_> = futures::executor::block_on(message_stream(reader).collect());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_ref().unwrap(), &Message::VersionMsg(first));
+        assert_eq!(messages[1].as_ref().unwrap(), &Message::ActiveCapacitanceMsg(second));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_replay_reader() {
+        use crate::*;
+        let first = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let second = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        let mut bytes = serialize_msg(&first);
+        bytes.extend(serialize_msg(&second));
+        // The second frame straddles the two records
+        let split = bytes.len() - 3;
+        let mut capture = Vec::new();
+        for record in [&bytes[..split], &bytes[split..]] {
+            capture.extend(&(record.len() as u16).to_le_bytes());
+            capture.extend(record);
+        }
+        let messages: Vec<_> = replay_reader(std::io::Cursor::new(&capture)).collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_ref().unwrap(), &Message::VersionMsg(first));
+        assert_eq!(messages[1].as_ref().unwrap(), &Message::ActiveCapacitanceMsg(second));
+
+        // Cut off part way through the second record
+        let messages: Vec<_> = replay_reader(&capture[..capture.len() - 1]).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].is_ok());
+        assert!(matches!(messages[1], Err(ParseError::TruncatedFrame)));
+
+        // Ending in a corrupted frame reports only its checksum failure
+        let n = bytes.len();
+        bytes[n - 1] ^= 0x01;
+        let mut capture = (n as u16).to_le_bytes().to_vec();
+        capture.extend(&bytes);
+        let messages: Vec<_> = replay_reader(std::io::Cursor::new(&capture)).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].is_ok());
+        assert!(matches!(messages[1], Err(ParseError::ChecksumError { id: ACTIVE_CAPACITANCE_ID, .. })));
+    }
+
+    #[test]
+    fn test_parse_with_registry() {
+        use crate::*;
+        #[derive(Debug, PartialEq)]
+        struct Telemetry { temperature: i16, flags: u8 }
+
+        let mut registry = DecoderRegistry::new();
+        registry.register(200, Some(3), |data: &[u8]| {
+            if data.len() != 3 {
+                return Err(ParseError::DeserializationError { id: 200, expected: 3, actual: data.len() });
+            }
+            Ok(Telemetry{ temperature: i16::from_le_bytes([data[0], data[1]]), flags: data[2] })
+        });
+        // The drive enable id is reserved without a message, so can be registered
+        registry.register(DRIVE_ENABLE_ID, Some(1), |data: &[u8]| Ok(Telemetry{ temperature: 0, flags: data[0] }));
+        let mut bytes = serialize_raw(200, &[0xfe, 0xff, 0x7e]);
+        bytes.extend(serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 }));
+        bytes.extend(serialize_raw(201, &[]));
+        bytes.extend(serialize_raw(DRIVE_ENABLE_ID, &[5]));
+        let mut parser = Parser::new();
+        let mut results = Vec::new();
+        for b in &bytes {
+            if let Some(result) = parser.parse_with_registry(*b, &registry).transpose() {
+                results.push(result);
+            }
+        }
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &Decoded::Custom(Telemetry{ temperature: -2, flags: 0x7e }));
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &Decoded::Message(Message::VersionMsg(VersionStruct{ major: 1, minor: 2, patch: 3 }))
+        );
+        assert!(matches!(results[2], Err(ParseError::UnknownPacketId(201))));
+        assert_eq!(results[3].as_ref().unwrap(), &Decoded::Custom(Telemetry{ temperature: 0, flags: 5 }));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_decode_from_buf() {
+        use crate::*;
+        let first = VersionStruct{ major: 1, minor: 2, patch: 3 };
+        let second = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        let mut bytes = serialize_msg(&first);
+        bytes.extend(serialize_msg(&second));
+        let split = 4;
+        let mut parser = Parser::new();
+        let mut buf = ::bytes::BytesMut::new();
+        buf.extend_from_slice(&bytes[..split]);
+        assert!(decode_from_buf(&mut buf, &mut parser).unwrap().is_none());
+        assert!(buf.is_empty());
+        buf.extend_from_slice(&bytes[split..]);
+        assert_eq!(decode_from_buf(&mut buf, &mut parser).unwrap(), Some(Message::VersionMsg(first)));
+        assert_eq!(&buf[..], &serialize_msg(&second)[..]);
+        assert_eq!(decode_from_buf(&mut buf, &mut parser).unwrap(), Some(Message::ActiveCapacitanceMsg(second)));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_parse_into_arena() {
+        use crate::*;
+        let scans: Vec<BulkCapacitanceStruct> = (0..4)
+            .map(|i| BulkCapacitanceStruct{ start_index: i, values: (0..20).map(|x| x * (i as u16 + 1)).collect() })
+            .collect();
+        let mut bytes = Vec::new();
+        for (i, scan) in scans.iter().enumerate() {
+            bytes.extend(serialize_msg(scan));
+            if i == 1 {
+                bytes.extend(serialize_msg(&VersionStruct{ major: 1, minor: 2, patch: 3 }));
+            }
+        }
+        let mut parser = Parser::new();
+        let mut arena = MessageArena::new();
+        let mut decoded = Vec::new();
+        let mut buffers = Vec::new();
+        for b in &bytes {
+            if let Some(msg) = parser.parse_into(*b, &mut arena).unwrap() {
+                if let Message::BulkCapacitanceMsg(scan) = msg {
+                    buffers.push(scan.values.as_ptr());
+                }
+                decoded.push(msg.clone());
+            }
+        }
+        assert_eq!(decoded.len(), 5);
+        assert_eq!(decoded[2], Message::VersionMsg(VersionStruct{ major: 1, minor: 2, patch: 3 }));
+        decoded.remove(2);
+        assert_eq!(decoded, scans.into_iter().map(Message::from).collect::<Vec<_>>());
+        assert_eq!(arena.message(), decoded.last());
+        // Two buffers take turns once the arena has warmed up
+        assert_eq!(buffers[2], buffers[0]);
+        assert_eq!(buffers[3], buffers[1]);
+    }
+
+    #[test]
+    fn test_frame_observer() {
+        use crate::*;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut parser = Parser::new();
+        let frames = seen.clone();
+        parser.set_frame_observer(move |id, payload| frames.borrow_mut().push((id, payload.to_vec())));
+        let msg = ActiveCapacitanceStruct{ baseline: 0x7e02, measurement: 0x504 };
+        let frame = serialize_msg(&msg);
+        // Frames failing their checksum aren't observed
+        let mut bytes = frame.clone();
+        bytes[frame.len() - 1] ^= 1;
+        bytes.extend(&frame);
+        let mut decoded = Vec::new();
+        for b in &bytes {
+            if let Ok(Some(msg)) = parser.parse(*b) {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, vec![Message::ActiveCapacitanceMsg(msg.clone())]);
+        assert_eq!(*seen.borrow(), vec![(ACTIVE_CAPACITANCE_ID, msg.payload())]);
+
+        // A clone doesn't call the observer
+        let mut copy = parser.clone();
+        assert!(parse_message(&mut copy, &frame).unwrap().is_some());
+        assert_eq!(seen.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_autodetect() {
+        use crate::*;
+        let stuffed_msg = ActiveCapacitanceStruct{ baseline: 0x7e, measurement: 0x7d };
+        let raw_msg = VersionStruct{ major: 0x7e, minor: 2, patch: 3 };
+        let mut bytes = serialize_msg(&stuffed_msg);
+        // A raw frame is length-prefixed and unescaped, without a start byte
+        bytes.extend(&serialize_raw_framed(FramingMode::LengthPrefixed, VERSION_ID, &raw_msg.payload())[1..]);
+        bytes.extend(serialize_msg(&stuffed_msg));
+        let mut parser = Parser::new();
+        let mut decoded = Vec::new();
+        for b in &bytes {
+            if let Some(msg) = parser.parse_autodetect(*b).unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, vec![
+            Message::ActiveCapacitanceMsg(stuffed_msg.clone()),
+            Message::VersionMsg(raw_msg),
+            Message::ActiveCapacitanceMsg(stuffed_msg.clone()),
+        ]);
+        assert!(!parser.is_parsing());
+
+        // Resetting part way through a raw frame leaves the parser byte-stuffed
+        let mut parser = Parser::new();
+        parser.parse_autodetect(5).unwrap();
+        parser.reset();
+        let rx = parse_message(&mut parser, &serialize_msg(&stuffed_msg)).unwrap();
+        assert_eq!(rx, Some(Message::ActiveCapacitanceMsg(stuffed_msg)));
+    }
+
+    #[cfg(feature = "crc32")]
+    #[test]
+    fn test_crc32_roundtrip() {
+        use crate::*;
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let msg = BulkCapacitanceStruct{ start_index: 0, values: (0..58).map(|x| x * 0x7d).collect() };
+        let bytes = serialize_msg_crc32(&msg);
+        let mut parser = Parser::new().with_crc32(true);
+        assert_eq!(parse_message(&mut parser, &bytes).unwrap(), Some(Message::BulkCapacitanceMsg(msg)));
+    }
+
+    #[cfg(feature = "crc32")]
+    #[test]
+    fn test_crc32_catches_corruption_checksum_misses() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct{ start_index: 0, values: (0..58).collect() };
+        let payload = msg.payload();
+        // Flipping the top bit of two bytes an even distance apart cancels out
+        // in a checksum summed mod 256. A single bit flip always changes it.
+        let mut corrupted = payload.clone();
+        corrupted[10] ^= 0x80;
+        corrupted[12] ^= 0x80;
+        let with_id = |p: &[u8]| [&[BULK_CAPACITANCE_ID][..], p].concat();
+        assert_eq!(checksum(&with_id(&payload)), checksum(&with_id(&corrupted)));
+        let mut bytes = serialize_raw(BULK_CAPACITANCE_ID, &corrupted);
+        escape_into(&crc32(&with_id(&payload)).to_le_bytes(), &mut bytes);
+        let mut parser = Parser::new().with_crc32(true);
+        assert!(matches!(
+            parse_message(&mut parser, &bytes),
+            Err(ParseError::CrcError { id: BULK_CAPACITANCE_ID, .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds 255")]
+    fn test_length_prefixed_payload_limit() {
+        use crate::*;
+        let bytes = serialize_raw_framed(FramingMode::LengthPrefixed, 1, &[0; 255]);
+        assert_eq!(bytes[1], 255);
+        serialize_raw_framed(FramingMode::LengthPrefixed, 1, &[0; 256]);
+    }
+
+    #[test]
+    fn test_length_prefixed_roundtrip() {
+        use crate::*;
+        let values: [u8; 16] = [0x7e, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0x7d, 0x7e];
+        let tx_msg = ElectrodeEnableStruct{ values };
+        let tx_bytes = serialize_raw_framed(FramingMode::LengthPrefixed, ELECTRODE_ENABLE_ID, &tx_msg.payload());
+        // No escaping: start + len + id + payload + checksum
+        assert_eq!(tx_bytes.len(), 16 + 5);
+        assert_eq!(tx_bytes[1], 16);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        let rx_msg = parse_message(&mut parser, &tx_bytes).unwrap();
+        assert_eq!(rx_msg, Some(Message::ElectrodeEnableMsg(tx_msg)));
+    }
+
+    #[test]
+    fn test_feed_multiple_frames() {
+        use crate::*;
+        let mut bytes = serialize_msg(&ActiveCapacitanceStruct{ baseline: 1, measurement: 2 });
+        bytes.extend(serialize_msg(&MoveStepperStruct{ steps: -10, period: 100 }));
+        bytes.extend(serialize_msg(&CommandAckStruct{ acked_id: MOVE_STEPPER_ID, status: 0 }));
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        // Split mid-frame to check partial state carries over
+        let split = bytes.len() - 2;
+        parser.feed(&bytes[..split], &mut out).unwrap();
+        assert_eq!(out.len(), 2);
+        parser.feed(&bytes[split..], &mut out).unwrap();
+        assert_eq!(out.len(), 3);
+        assert!(matches!(out[2], Message::CommandAckMsg(_)));
+    }
+
+    #[test]
+    fn test_lenient_parser_keeps_unknown_frames() {
+        use crate::*;
+        let tx_bytes = serialize_raw_framed(FramingMode::LengthPrefixed, 200, &[1, 0x7e, 3]);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        assert!(matches!(parse_message(&mut parser, &tx_bytes), Err(ParseError::UnknownPacketId(200))));
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed).with_lenient(true);
+        let rx_msg = parse_message(&mut parser, &tx_bytes).unwrap();
+        assert_eq!(rx_msg, Some(Message::RawMessage { id: 200, payload: vec![1, 0x7e, 3] }));
+    }
+
+    #[test]
+    fn test_last_bad_frame() {
+        use crate::*;
+        let mut bytes = vec![0x7e, ACTIVE_CAPACITANCE_ID, 2, 3, 4, 5, 0x11, 0x22];
+        let mut parser = Parser::new().with_retain_bad_frame(true);
+        assert!(parse_message(&mut parser, &bytes).is_err());
+        assert_eq!(parser.last_bad_frame(), Some(&bytes[1..]));
+
+        // Cleared once the next frame starts
+        bytes.truncate(1);
+        parse_message(&mut parser, &bytes).unwrap();
+        assert_eq!(parser.last_bad_frame(), None);
+    }
+
+    #[test]
+    fn test_clone_parser_mid_frame() {
+        use crate::*;
+        let bytes = serialize_msg(&ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 });
+        let (head, tail) = bytes.split_at(3);
+        let mut parser = Parser::default();
+        assert_eq!(parse_message(&mut parser, head).unwrap(), None);
+        let mut snapshot = parser.clone();
+        let first = parse_message(&mut parser, tail).unwrap();
+        assert!(first.is_some());
+        // The original has moved on, but the clone still completes the frame
+        assert!(parser.in_progress().is_none());
+        assert_eq!(parse_message(&mut snapshot, tail).unwrap(), first);
+    }
+
+    #[test]
+    fn test_size_overrun_reported() {
+        use crate::*;
+        // Declares 100 values, which is more than the buffer can hold
+        let mut bytes = vec![0x7e, BULK_CAPACITANCE_ID, 0, 100];
+        bytes.extend(&[1u8; 200]);
+        let mut parser = Parser::new();
+        assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::SizeOverrun)));
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected_early() {
+        use crate::*;
+        // A count of 255 declares a 512 byte payload
+        let bytes = [0x7e, BULK_CAPACITANCE_ID, 0, 255];
+        let mut parser = Parser::new();
+        for b in &bytes[..3] {
+            assert_eq!(parser.parse(*b).unwrap(), None);
+        }
+        assert!(matches!(parser.parse(bytes[3]), Err(ParseError::SizeOverrun)));
+        assert!(!parser.is_parsing());
+
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        parser.parse(0x7e).unwrap();
+        assert!(matches!(parser.parse(200), Err(ParseError::SizeOverrun)));
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_serialize_heapless() {
+        use crate::*;
+        let msg = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        let expected = serialize_msg(&msg);
+        assert_eq!(expected.len(), 8);
+        let bytes = serialize_msg_heapless::<_, 8>(&msg).unwrap();
+        assert_eq!(&bytes[..], &expected[..]);
+        assert!(matches!(serialize_msg_heapless::<_, 7>(&msg), Err(ParseError::BufferTooSmall)));
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_payload_heapless() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct{ start_index: 1, values: vec![2, 3] };
+        let payload = msg.payload_heapless::<6>().unwrap();
+        assert_eq!(&payload[..], &msg.payload()[..]);
+        assert!(matches!(msg.payload_heapless::<5>(), Err(ParseError::BufferTooSmall)));
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_encode_into_deque() {
+        use crate::*;
+        let msg: Message = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 }.into();
+        let frame = msg.clone().into_bytes();
+        let mut q = heapless::Deque::<u8, 12>::new();
+        encode_into_deque(&msg, &mut q).unwrap();
+        assert!(q.iter().eq(frame.iter()));
+        // Only 4 bytes left, so the second frame is rejected whole
+        assert!(matches!(encode_into_deque(&msg, &mut q), Err(ParseError::BufferTooSmall)));
+        assert_eq!(q.len(), frame.len());
+        assert!(q.iter().eq(frame.iter()));
+    }
+
+    #[test]
+    fn test_decode_iter() {
+        use crate::*;
+        let mut bytes = serialize_msg(&ActiveCapacitanceStruct{ baseline: 1, measurement: 2 });
+        bytes.extend(serialize_msg(&VersionStruct{ major: 1, minor: 0, patch: 0 }));
+        let decoded: Vec<Message> = decode_iter(bytes).map(|m| m.unwrap()).collect();
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(decoded[0], Message::ActiveCapacitanceMsg(_)));
+        assert!(matches!(decoded[1], Message::VersionMsg(_)));
+    }
+
+    #[test]
+    fn test_short_electrode_frame_does_not_corrupt_next() {
+        use crate::*;
+        let mut bytes = serialize_raw(ELECTRODE_ENABLE_ID, &[1; 10]);
+        let msg = ActiveCapacitanceStruct{ baseline: 0x302, measurement: 0x504 };
+        bytes.extend(serialize_msg(&msg));
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        parser.feed(&bytes, &mut out).unwrap();
+        assert_eq!(out, vec![Message::ActiveCapacitanceMsg(msg)]);
+    }
+
+    #[test]
+    fn test_decode_error_surfaced() {
+        use crate::*;
+        // With an explicit length the short frame completes, and the decode
+        // failure is reported
+        let bytes = serialize_raw_framed(FramingMode::LengthPrefixed, ELECTRODE_ENABLE_ID, &[1; 10]);
+        let mut parser = Parser::new_with_mode(FramingMode::LengthPrefixed);
+        match parse_message(&mut parser, &bytes) {
+            Err(ParseError::DeserializationError { id, expected, actual }) => {
+                assert_eq!(id, ELECTRODE_ENABLE_ID);
+                assert_eq!(expected, 16);
+                assert_eq!(actual, 10);
+            },
+            other => panic!("Expected deserialization error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_error_from_complete_frame() {
+        use crate::*;
+        // A byte-stuffed frame of the expected size with a valid checksum,
+        // whose payload is still rejected when decoded
+        let bytes = serialize_raw(REBOOT_ID, &[0, 0, 0, 0]);
+        let mut parser = Parser::new();
+        assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::InvalidValue { id: REBOOT_ID })));
+
+        // Unknown ids are reported too, unless the parser is lenient
+        let bytes = serialize_raw(200, &[]);
+        let mut parser = Parser::new();
+        assert!(matches!(parse_message(&mut parser, &bytes), Err(ParseError::UnknownPacketId(200))));
+    }
+
+    #[test]
+    fn test_serialize_into_matches_vec() {
+        use crate::*;
+        let values: [u8; 16] = [0x7e, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0x7d, 0x7e];
+        let msg = ElectrodeEnableStruct{ values };
+        let expected = serialize_msg(&msg);
+        let mut buf = [0u8; 64];
+        let len = serialize_msg_into(&msg, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+        assert!(serialize_msg_into(&msg, &mut buf[..expected.len() - 1]).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod proptests {
+    use crate::alloc::vec::Vec;
+    use crate::*;
+    use proptest::prelude::*;
+
+    fn arb_message() -> impl Strategy<Value = Message> {
+        // Bulk capacitance messages are limited to what fits in the working buffer
+        prop_oneof![
+            any::<[u8; 16]>().prop_map(|values| ElectrodeEnableStruct{values}.into()),
+            (any::<u8>(), proptest::collection::vec(any::<u16>(), 0..=61))
+                .prop_map(|(start_index, values)| BulkCapacitanceStruct{start_index, values: values.into_iter().collect()}.into()),
+            (any::<u16>(), any::<u16>())
+                .prop_map(|(baseline, measurement)| ActiveCapacitanceStruct{baseline, measurement}.into()),
+            (any::<u8>(), any::<u8>())
+                .prop_map(|(acked_id, status)| CommandAckStruct{acked_id, status}.into()),
+            (any::<i16>(), any::<u16>())
+                .prop_map(|(steps, period)| MoveStepperStruct{steps, period}.into()),
+            (any::<u8>(), any::<u8>(), any::<u8>())
+                .prop_map(|(major, minor, patch)| VersionStruct{major, minor, patch}.into()),
+            (any::<u8>(), proptest::collection::vec(any::<u32>(), 0..=30))
+                .prop_map(|(start_index, values)| BulkCapacitanceWideStruct{start_index, values}.into()),
+            any::<u16>().prop_map(|key| ConfigGetStruct{key}.into()),
+            (any::<u16>(), any::<u32>())
+                .prop_map(|(key, value)| ConfigSetStruct{key, value}.into()),
+            Just(RebootStruct{magic: RebootStruct::MAGIC}.into()),
+            proptest::collection::vec(any::<(u8, i16, u16)>(), 0..=24)
+                .prop_map(|moves| MoveStepperGroupStruct{moves}.into()),
+            (any::<u16>(), any::<u32>())
+                .prop_map(|(code, detail)| FaultStruct{code, detail}.into()),
+            (1..=u32::MAX, any::<u16>())
+                .prop_map(|(frequency_hz, voltage_mv)| DriveParamsStruct{frequency_hz, voltage_mv}.into()),
+            (any::<u8>(), proptest::collection::vec(any::<u8>(), 0..=100))
+                .prop_map(|(index, name)| LabelStruct{index, name}.into()),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip_through_parser(msg in arb_message()) {
+            let bytes = serialize_raw(msg.id(), &msg.payload());
+            let mut parser = Parser::new();
+            let mut decoded = Vec::new();
+            for b in &bytes {
+                if let Some(rx) = parser.parse(*b).unwrap() {
+                    decoded.push(rx);
+                }
+            }
+            prop_assert_eq!(decoded, vec![msg]);
+        }
+
+        #[test]
+        fn reserialize_is_byte_identical(msg in arb_message()) {
+            let bytes = serialize_raw(msg.id(), &msg.payload());
+            let mut parser = Parser::new();
+            let mut reserialized = Vec::new();
+            for b in &bytes {
+                if let Some(rx) = parser.parse(*b).unwrap() {
+                    reserialized.extend(rx.serialize());
+                }
+            }
+            prop_assert_eq!(reserialized, bytes);
+        }
+
+        #[test]
+        fn payload_len_matches_payload(msg in arb_message()) {
+            prop_assert_eq!(msg.payload_len(), msg.payload().len());
+        }
+    }
+}
+
+/// Exercises the parser in a decode-only build, where none of the serializers
+/// exist; run with `cargo test --no-default-features --features decode,bounded-bulk`
+/// to also check that decoding doesn't need an allocator
+#[cfg(all(test, feature = "decode", not(feature = "encode")))]
+mod decode_only_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_without_encode() {
+        // ActiveCapacitance { baseline: 0x1234, measurement: 0x5678 }, framed by hand
+        let frame = [0x7e, ACTIVE_CAPACITANCE_ID, 0x34, 0x12, 0x78, 0x56, 0x17, 0x5b];
+        assert_eq!(checksum(&frame[1..6]), (0x17, 0x5b));
+
+        let mut parser = Parser::new();
+        let mut arena = MessageArena::new();
+        let mut msg = None;
+        for b in frame {
+            if let Some(m) = parser.parse_into(b, &mut arena).unwrap() {
+                msg = Some(m.clone());
+            }
+        }
+        match msg {
+            Some(Message::ActiveCapacitanceMsg(m)) => {
+                assert_eq!(m.baseline, 0x1234);
+                assert_eq!(m.measurement, 0x5678);
+            },
+            other => panic!("unexpected {:?}", other),
         }
     }
 }
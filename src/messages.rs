@@ -1,331 +1,2969 @@
 use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "encode")]
+use super::alloc::vec;
+#[cfg(all(feature = "decode", feature = "alloc"))]
+use super::alloc::boxed::Box;
+#[cfg(feature = "alloc")]
 use super::alloc::vec::Vec;
+use super::codec::*;
 use super::error::ParseError;
+use super::fixed_payload_size;
 
 pub const ELECTRODE_ENABLE_ID: u8 = 0;
+/// Reserved, with no message defined for it
 pub const DRIVE_ENABLE_ID: u8 = 1;
 pub const BULK_CAPACITANCE_ID: u8 = 2;
 pub const ACTIVE_CAPACITANCE_ID: u8 = 3;
 pub const COMMAND_ACK_ID: u8 = 4;
 pub const MOVE_STEPPER_ID: u8 = 5;
+pub const VERSION_ID: u8 = 6;
+pub const BULK_CAPACITANCE_WIDE_ID: u8 = 7;
+pub const CONFIG_GET_ID: u8 = 8;
+pub const CONFIG_SET_ID: u8 = 9;
+pub const REBOOT_ID: u8 = 10;
+pub const MOVE_STEPPER_GROUP_ID: u8 = 11;
+pub const FAULT_ID: u8 = 12;
+pub const DRIVE_PARAMS_ID: u8 = 13;
+pub const LABEL_ID: u8 = 14;
 
-#[derive(Debug, Clone)]
+/// Worst-case number of bytes on the wire for a frame carrying `payload_len`
+/// payload bytes
+///
+/// Every byte of the id, payload and checksum may need escaping, so each
+/// takes up to two bytes, plus one for the start-of-frame delimiter.
+pub const fn max_framed_len(payload_len: usize) -> usize {
+    2 * (payload_len + 3) + 1
+}
+
+/// Every message id known to this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum MessageId {
+    ElectrodeEnable = ELECTRODE_ENABLE_ID,
+    BulkCapacitance = BULK_CAPACITANCE_ID,
+    ActiveCapacitance = ACTIVE_CAPACITANCE_ID,
+    CommandAck = COMMAND_ACK_ID,
+    MoveStepper = MOVE_STEPPER_ID,
+    Version = VERSION_ID,
+    BulkCapacitanceWide = BULK_CAPACITANCE_WIDE_ID,
+    ConfigGet = CONFIG_GET_ID,
+    ConfigSet = CONFIG_SET_ID,
+    Reboot = REBOOT_ID,
+    MoveStepperGroup = MOVE_STEPPER_GROUP_ID,
+    Fault = FAULT_ID,
+    DriveParams = DRIVE_PARAMS_ID,
+    Label = LABEL_ID,
+}
+
+impl MessageId {
+    /// All known ids, in ascending order
+    pub const ALL: &'static [MessageId] = &[
+        MessageId::ElectrodeEnable,
+        MessageId::BulkCapacitance,
+        MessageId::ActiveCapacitance,
+        MessageId::CommandAck,
+        MessageId::MoveStepper,
+        MessageId::Version,
+        MessageId::BulkCapacitanceWide,
+        MessageId::ConfigGet,
+        MessageId::ConfigSet,
+        MessageId::Reboot,
+        MessageId::MoveStepperGroup,
+        MessageId::Fault,
+        MessageId::DriveParams,
+        MessageId::Label,
+    ];
+
+    pub fn from_id(id: u8) -> Option<MessageId> {
+        match id {
+            ELECTRODE_ENABLE_ID => Some(MessageId::ElectrodeEnable),
+            BULK_CAPACITANCE_ID => Some(MessageId::BulkCapacitance),
+            ACTIVE_CAPACITANCE_ID => Some(MessageId::ActiveCapacitance),
+            COMMAND_ACK_ID => Some(MessageId::CommandAck),
+            MOVE_STEPPER_ID => Some(MessageId::MoveStepper),
+            VERSION_ID => Some(MessageId::Version),
+            BULK_CAPACITANCE_WIDE_ID => Some(MessageId::BulkCapacitanceWide),
+            CONFIG_GET_ID => Some(MessageId::ConfigGet),
+            CONFIG_SET_ID => Some(MessageId::ConfigSet),
+            REBOOT_ID => Some(MessageId::Reboot),
+            MOVE_STEPPER_GROUP_ID => Some(MessageId::MoveStepperGroup),
+            FAULT_ID => Some(MessageId::Fault),
+            DRIVE_PARAMS_ID => Some(MessageId::DriveParams),
+            LABEL_ID => Some(MessageId::Label),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Human-readable name, for logging
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageId::ElectrodeEnable => "ElectrodeEnable",
+            MessageId::BulkCapacitance => "BulkCap",
+            MessageId::ActiveCapacitance => "ActiveCap",
+            MessageId::CommandAck => "CommandAck",
+            MessageId::MoveStepper => "MoveStepper",
+            MessageId::Version => "Version",
+            MessageId::BulkCapacitanceWide => "BulkCapWide",
+            MessageId::ConfigGet => "ConfigGet",
+            MessageId::ConfigSet => "ConfigSet",
+            MessageId::Reboot => "Reboot",
+            MessageId::MoveStepperGroup => "MoveStepperGroup",
+            MessageId::Fault => "Fault",
+            MessageId::DriveParams => "DriveParams",
+            MessageId::Label => "Label",
+        }
+    }
+}
+
+const fn all_id_bytes() -> [u8; MessageId::ALL.len()] {
+    let mut ids = [0; MessageId::ALL.len()];
+    let mut i = 0;
+    while i < ids.len() {
+        ids[i] = MessageId::ALL[i] as u8;
+        i += 1;
+    }
+    ids
+}
+
+/// Human-readable name of the message with the given id, for logging
+///
+/// Returns "Unknown" for ids this crate doesn't recognize.
+pub fn message_name(id: u8) -> &'static str {
+    Message::id_name(id).unwrap_or("Unknown")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Message {
     ElectrodeEnableMsg(ElectrodeEnableStruct),
+    #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
     BulkCapacitanceMsg(BulkCapacitanceStruct),
     ActiveCapacitanceMsg(ActiveCapacitanceStruct),
     CommandAckMsg(CommandAckStruct),
     MoveStepperMsg(MoveStepperStruct),
+    VersionMsg(VersionStruct),
+    #[cfg(feature = "alloc")]
+    BulkCapacitanceWideMsg(BulkCapacitanceWideStruct),
+    ConfigGetMsg(ConfigGetStruct),
+    ConfigSetMsg(ConfigSetStruct),
+    RebootMsg(RebootStruct),
+    #[cfg(feature = "alloc")]
+    MoveStepperGroupMsg(MoveStepperGroupStruct),
+    FaultMsg(FaultStruct),
+    DriveParamsMsg(DriveParamsStruct),
+    #[cfg(feature = "alloc")]
+    LabelMsg(LabelStruct),
+    /// A frame with an id this crate doesn't recognize, kept undecoded
+    #[cfg(feature = "alloc")]
+    RawMessage { id: u8, payload: Vec<u8> },
+}
+
+/// Which variant a `Message` is, without its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MessageKind {
+    ElectrodeEnable,
+    BulkCapacitance,
+    ActiveCapacitance,
+    CommandAck,
+    MoveStepper,
+    Version,
+    BulkCapacitanceWide,
+    ConfigGet,
+    ConfigSet,
+    Reboot,
+    MoveStepperGroup,
+    Fault,
+    DriveParams,
+    Label,
+    Raw,
 }
 
 impl Message {
+    /// Every message id known to this crate, in ascending order
+    pub const ALL_IDS: &'static [u8] = &all_id_bytes();
+
+    /// Name of the message with the given id, or None if the id is unknown
+    pub fn id_name(id: u8) -> Option<&'static str> {
+        MessageId::from_id(id).map(MessageId::name)
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(_) => MessageKind::ElectrodeEnable,
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+            BulkCapacitanceMsg(_) => MessageKind::BulkCapacitance,
+            ActiveCapacitanceMsg(_) => MessageKind::ActiveCapacitance,
+            CommandAckMsg(_) => MessageKind::CommandAck,
+            MoveStepperMsg(_) => MessageKind::MoveStepper,
+            VersionMsg(_) => MessageKind::Version,
+            #[cfg(feature = "alloc")]
+            BulkCapacitanceWideMsg(_) => MessageKind::BulkCapacitanceWide,
+            ConfigGetMsg(_) => MessageKind::ConfigGet,
+            ConfigSetMsg(_) => MessageKind::ConfigSet,
+            RebootMsg(_) => MessageKind::Reboot,
+            #[cfg(feature = "alloc")]
+            MoveStepperGroupMsg(_) => MessageKind::MoveStepperGroup,
+            FaultMsg(_) => MessageKind::Fault,
+            DriveParamsMsg(_) => MessageKind::DriveParams,
+            #[cfg(feature = "alloc")]
+            LabelMsg(_) => MessageKind::Label,
+            #[cfg(feature = "alloc")]
+            RawMessage { .. } => MessageKind::Raw,
+        }
+    }
+
     /// Return the expected payload size for the message, if it can be determined
     /// The size can depend on the data, and so it may not be known until sufficient
     /// bytes are received.
     pub fn message_size(id: u8, data: &[u8]) -> Option<usize> {
         match id {
             ELECTRODE_ENABLE_ID => ElectrodeEnableStruct::message_size(data),
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
             BULK_CAPACITANCE_ID => BulkCapacitanceStruct::message_size(data),
             ACTIVE_CAPACITANCE_ID => ActiveCapacitanceStruct::message_size(data),
             COMMAND_ACK_ID => CommandAckStruct::message_size(data),
             MOVE_STEPPER_ID => MoveStepperStruct::message_size(data),
+            VERSION_ID => VersionStruct::message_size(data),
+            #[cfg(feature = "alloc")]
+            BULK_CAPACITANCE_WIDE_ID => BulkCapacitanceWideStruct::message_size(data),
+            CONFIG_GET_ID => ConfigGetStruct::message_size(data),
+            CONFIG_SET_ID => ConfigSetStruct::message_size(data),
+            REBOOT_ID => RebootStruct::message_size(data),
+            #[cfg(feature = "alloc")]
+            MOVE_STEPPER_GROUP_ID => MoveStepperGroupStruct::message_size(data),
+            FAULT_ID => FaultStruct::message_size(data),
+            DRIVE_PARAMS_ID => DriveParamsStruct::message_size(data),
+            #[cfg(feature = "alloc")]
+            LABEL_ID => LabelStruct::message_size(data),
             _ => Some(0),
         }
     }
 
+    /// Like `message_size`, but the shortest payload which is still valid for
+    /// messages which also have a shorter legacy form
+    pub fn min_message_size(id: u8, data: &[u8]) -> Option<usize> {
+        match id {
+            COMMAND_ACK_ID => CommandAckStruct::min_message_size(data),
+            _ => Message::message_size(id, data),
+        }
+    }
+
     pub fn from_payload(id: u8, data: &[u8]) -> Result<Message, ParseError> {
         use Message::*;
         match id {
             ELECTRODE_ENABLE_ID => Ok(ElectrodeEnableMsg(ElectrodeEnableStruct::try_from(data)?)),
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
             BULK_CAPACITANCE_ID => Ok(BulkCapacitanceMsg(BulkCapacitanceStruct::try_from(data)?)),
             ACTIVE_CAPACITANCE_ID => Ok(ActiveCapacitanceMsg(ActiveCapacitanceStruct::try_from(data)?)),
             COMMAND_ACK_ID => Ok(CommandAckMsg(CommandAckStruct::try_from(data)?)),
             MOVE_STEPPER_ID => Ok(MoveStepperMsg(MoveStepperStruct::try_from(data)?)),
+            VERSION_ID => Ok(VersionMsg(VersionStruct::try_from(data)?)),
+            #[cfg(feature = "alloc")]
+            BULK_CAPACITANCE_WIDE_ID => Ok(BulkCapacitanceWideMsg(BulkCapacitanceWideStruct::try_from(data)?)),
+            CONFIG_GET_ID => Ok(ConfigGetMsg(ConfigGetStruct::try_from(data)?)),
+            CONFIG_SET_ID => Ok(ConfigSetMsg(ConfigSetStruct::try_from(data)?)),
+            REBOOT_ID => Ok(RebootMsg(RebootStruct::try_from(data)?)),
+            #[cfg(feature = "alloc")]
+            MOVE_STEPPER_GROUP_ID => Ok(MoveStepperGroupMsg(MoveStepperGroupStruct::try_from(data)?)),
+            FAULT_ID => Ok(FaultMsg(FaultStruct::try_from(data)?)),
+            DRIVE_PARAMS_ID => Ok(DriveParamsMsg(DriveParamsStruct::try_from(data)?)),
+            #[cfg(feature = "alloc")]
+            LABEL_ID => Ok(LabelMsg(LabelStruct::try_from(data)?)),
             _ => Err(ParseError::UnknownPacketId(id)),
         }
     }
-}
 
-pub trait MessageStruct {
-    fn id(&self) -> u8;
+    /// Decode a message from the start of `data`, returning it along with the
+    /// number of bytes it used
+    ///
+    /// Any bytes after the size declared for the message are left alone, so
+    /// `data` may carry trailing data.
+    pub fn from_payload_with_len(id: u8, data: &[u8]) -> Result<(Message, usize), ParseError> {
+        let len = match Message::message_size(id, data) {
+            Some(len) if len <= data.len() => len,
+            // Too short; decode it all to report the error
+            _ => data.len(),
+        };
+        Ok((Message::from_payload(id, &data[..len])?, len))
+    }
 
-    fn payload(&self) -> Vec<u8>;
+    /// Like `from_payload`, but decoding multi-byte fields in the given byte order
+    ///
+    /// Only bulk capacitance, active capacitance and move stepper messages
+    /// support big-endian encoding; other messages always decode as
+    /// little-endian.
+    pub fn from_payload_endian(id: u8, data: &[u8], endian: Endian) -> Result<Message, ParseError> {
+        use Message::*;
+        match id {
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+            BULK_CAPACITANCE_ID => Ok(BulkCapacitanceMsg(BulkCapacitanceStruct::from_payload_endian(data, endian)?)),
+            ACTIVE_CAPACITANCE_ID => Ok(ActiveCapacitanceMsg(ActiveCapacitanceStruct::from_payload_endian(data, endian)?)),
+            MOVE_STEPPER_ID => Ok(MoveStepperMsg(MoveStepperStruct::from_payload_endian(data, endian)?)),
+            _ => Message::from_payload(id, data),
+        }
+    }
 
-    /// Returns the size of the message payload if it is known,
-    /// or None if it cannot yet be determined (i.e. because it depends on
-    /// message content not yet recieved)
+    /// Like `from_payload`, but a frame with an unrecognized id is returned as
+    /// a `RawMessage` rather than an error
     ///
-    /// `data` is the payload contents received so far, it may be a partial
-    /// message.
-    fn message_size(data: &[u8]) -> Option<usize>;
-}
+    /// `message_size` can't know the payload length for an unknown id, so when
+    /// parsing byte-stuffed frames this only recovers frames with an empty
+    /// payload; use length-prefixed or COBS framing to preserve unknown frames.
+    #[cfg(feature = "alloc")]
+    pub fn from_payload_lenient(id: u8, data: &[u8]) -> Result<Message, ParseError> {
+        match Message::from_payload(id, data) {
+            Err(ParseError::UnknownPacketId(_)) => Ok(Message::RawMessage { id, payload: data.into() }),
+            result => result,
+        }
+    }
 
-#[derive(Debug, Clone)]
-pub struct CommandAckStruct {
-    pub acked_id: u8,
-}
+    /// Upper bound on the serialized length of the message, including framing
+    /// and worst-case escaping
+    #[cfg(feature = "encode")]
+    pub fn serialized_max_len(&self) -> usize {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(msg) => msg.serialized_max_len(),
+            BulkCapacitanceMsg(msg) => msg.serialized_max_len(),
+            ActiveCapacitanceMsg(msg) => msg.serialized_max_len(),
+            CommandAckMsg(msg) => msg.serialized_max_len(),
+            MoveStepperMsg(msg) => msg.serialized_max_len(),
+            VersionMsg(msg) => msg.serialized_max_len(),
+            BulkCapacitanceWideMsg(msg) => msg.serialized_max_len(),
+            ConfigGetMsg(msg) => msg.serialized_max_len(),
+            ConfigSetMsg(msg) => msg.serialized_max_len(),
+            RebootMsg(msg) => msg.serialized_max_len(),
+            MoveStepperGroupMsg(msg) => msg.serialized_max_len(),
+            FaultMsg(msg) => msg.serialized_max_len(),
+            DriveParamsMsg(msg) => msg.serialized_max_len(),
+            LabelMsg(msg) => msg.serialized_max_len(),
+            RawMessage { payload, .. } => max_framed_len(payload.len()),
+        }
+    }
 
-impl MessageStruct for CommandAckStruct {
-    fn id(&self) -> u8 {
-        COMMAND_ACK_ID
+    /// Exact length of the unescaped payload, the counterpart to
+    /// `serialized_max_len`
+    #[cfg(feature = "encode")]
+    pub fn payload_len(&self) -> usize {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(msg) => msg.payload_len(),
+            BulkCapacitanceMsg(msg) => msg.payload_len(),
+            ActiveCapacitanceMsg(msg) => msg.payload_len(),
+            CommandAckMsg(msg) => msg.payload_len(),
+            MoveStepperMsg(msg) => msg.payload_len(),
+            VersionMsg(msg) => msg.payload_len(),
+            BulkCapacitanceWideMsg(msg) => msg.payload_len(),
+            ConfigGetMsg(msg) => msg.payload_len(),
+            ConfigSetMsg(msg) => msg.payload_len(),
+            RebootMsg(msg) => msg.payload_len(),
+            MoveStepperGroupMsg(msg) => msg.payload_len(),
+            FaultMsg(msg) => msg.payload_len(),
+            DriveParamsMsg(msg) => msg.payload_len(),
+            LabelMsg(msg) => msg.payload_len(),
+            RawMessage { payload, .. } => payload.len(),
+        }
     }
 
-    fn payload(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.push(self.acked_id);
-        buf
+    /// The id the message is sent with
+    pub fn id(&self) -> u8 {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(msg) => msg.id(),
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+            BulkCapacitanceMsg(msg) => msg.id(),
+            ActiveCapacitanceMsg(msg) => msg.id(),
+            CommandAckMsg(msg) => msg.id(),
+            MoveStepperMsg(msg) => msg.id(),
+            VersionMsg(msg) => msg.id(),
+            #[cfg(feature = "alloc")]
+            BulkCapacitanceWideMsg(msg) => msg.id(),
+            ConfigGetMsg(msg) => msg.id(),
+            ConfigSetMsg(msg) => msg.id(),
+            RebootMsg(msg) => msg.id(),
+            #[cfg(feature = "alloc")]
+            MoveStepperGroupMsg(msg) => msg.id(),
+            FaultMsg(msg) => msg.id(),
+            DriveParamsMsg(msg) => msg.id(),
+            #[cfg(feature = "alloc")]
+            LabelMsg(msg) => msg.id(),
+            #[cfg(feature = "alloc")]
+            RawMessage { id, .. } => *id,
+        }
     }
 
-    fn message_size(_data: &[u8]) -> Option<usize> {
-        Some(1)
+    /// The encoded payload, as for `MessageStruct::payload`
+    #[cfg(feature = "encode")]
+    pub fn payload(&self) -> Vec<u8> {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(msg) => msg.payload(),
+            BulkCapacitanceMsg(msg) => msg.payload(),
+            ActiveCapacitanceMsg(msg) => msg.payload(),
+            CommandAckMsg(msg) => msg.payload(),
+            MoveStepperMsg(msg) => msg.payload(),
+            VersionMsg(msg) => msg.payload(),
+            BulkCapacitanceWideMsg(msg) => msg.payload(),
+            ConfigGetMsg(msg) => msg.payload(),
+            ConfigSetMsg(msg) => msg.payload(),
+            RebootMsg(msg) => msg.payload(),
+            MoveStepperGroupMsg(msg) => msg.payload(),
+            FaultMsg(msg) => msg.payload(),
+            DriveParamsMsg(msg) => msg.payload(),
+            LabelMsg(msg) => msg.payload(),
+            RawMessage { payload, .. } => payload.clone(),
+        }
     }
-}
 
-impl TryFrom<&[u8]> for CommandAckStruct {
-    type Error = ParseError;
+    /// Get transmittable bytes for the message, like `into_bytes` but without
+    /// consuming it, e.g. to forward a message which is also kept
+    ///
+    /// Every field has exactly one encoding, so re-serializing a parsed message
+    /// reproduces the frame it was parsed from. That only holds for canonical
+    /// frames, i.e. those this crate would produce: a sender which escapes
+    /// bytes that don't need it, or appends trailing payload parsed leniently,
+    /// gets normalized.
+    #[cfg(feature = "encode")]
+    pub fn serialize(&self) -> Vec<u8> {
+        super::serialize_raw(self.id(), &self.payload())
+    }
 
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() < 1 {
-            return Err(ParseError::DeserializationError);
+    /// Consume the message, returning its transmittable bytes as from
+    /// `serialize_msg`
+    ///
+    /// Produces the same bytes as `serialize`, but skips copying the payload of
+    /// a `RawMessage`.
+    #[cfg(feature = "encode")]
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            // The payload is already encoded, so use it without a copy
+            Message::RawMessage { id, payload } => super::serialize_raw(id, &payload),
+            msg => msg.serialize(),
+        }
+    }
+
+    /// Consume the message, returning its id and encoded payload
+    ///
+    /// The reverse of `Message::try_from((id, &payload[..]))`.
+    #[cfg(feature = "encode")]
+    pub fn split(self) -> (u8, Vec<u8>) {
+        match self {
+            Message::RawMessage { id, payload } => (id, payload),
+            msg => (msg.id(), msg.payload()),
         }
-        Ok(Self{acked_id: data[0]})
     }
 }
 
+/// Spare buffers of one element type
+///
+/// Two are enough to stop allocating once warmed up, even with other messages
+/// interleaved, since the arena's current message holds a third.
+#[cfg(all(feature = "decode", feature = "alloc"))]
 #[derive(Debug, Clone)]
-pub struct ElectrodeEnableStruct {
-    pub values: [u8; 16],
-}
+struct Spares<T>([Vec<T>; 2]);
 
-impl MessageStruct for ElectrodeEnableStruct {
-    fn id(&self) -> u8 {
-        ELECTRODE_ENABLE_ID
+#[cfg(all(feature = "decode", feature = "alloc"))]
+impl<T> Default for Spares<T> {
+    fn default() -> Self {
+        Spares([Vec::new(), Vec::new()])
     }
+}
 
-    fn payload(&self) -> Vec<u8> {
-        self.values[..].into()
+#[cfg(all(feature = "decode", feature = "alloc"))]
+impl<T> Spares<T> {
+    /// Take the largest spare, or an empty Vec if there are none
+    fn take(&mut self) -> Vec<T> {
+        let i = if self.0[0].capacity() >= self.0[1].capacity() { 0 } else { 1 };
+        core::mem::take(&mut self.0[i])
     }
 
-    fn message_size(_data: &[u8]) -> Option<usize> {
-        Some(16)
+    /// Keep `buf` if it's larger than one of the spares
+    fn put(&mut self, mut buf: Vec<T>) {
+        buf.clear();
+        let i = if self.0[0].capacity() <= self.0[1].capacity() { 0 } else { 1 };
+        if buf.capacity() > self.0[i].capacity() {
+            self.0[i] = buf;
+        }
     }
 }
 
-impl TryFrom<&[u8]> for ElectrodeEnableStruct {
-    type Error = ParseError;
+/// Value buffers recycled from earlier messages, to be refilled when decoding
+/// the next ones
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodePool {
+    #[cfg(not(feature = "bounded-bulk"))]
+    bulk: Spares<u16>,
+    #[cfg(feature = "alloc")]
+    bulk_wide: Spares<u32>,
+}
 
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() != 16 {
-            return Err(ParseError::DeserializationError);
+#[cfg(feature = "decode")]
+impl DecodePool {
+    /// Decode as `Message::from_payload_endian`, taking buffers from the pool
+    pub(crate) fn decode(&mut self, id: u8, data: &[u8], endian: Endian) -> Result<Message, ParseError> {
+        match id {
+            #[cfg(not(feature = "bounded-bulk"))]
+            BULK_CAPACITANCE_ID => {
+                let mut msg = BulkCapacitanceStruct{start_index: 0, values: self.bulk.take()};
+                match msg.decode_into(data, endian) {
+                    Ok(()) => Ok(msg.into()),
+                    Err(e) => {
+                        self.bulk.put(msg.values);
+                        Err(e)
+                    },
+                }
+            },
+            #[cfg(feature = "alloc")]
+            BULK_CAPACITANCE_WIDE_ID => {
+                let mut msg = BulkCapacitanceWideStruct{start_index: 0, values: self.bulk_wide.take()};
+                match msg.decode_into(data) {
+                    Ok(()) => Ok(msg.into()),
+                    Err(e) => {
+                        self.bulk_wide.put(msg.values);
+                        Err(e)
+                    },
+                }
+            },
+            _ => Message::from_payload_endian(id, data, endian),
         }
-        let mut values = [0u8; 16];
-        for i in 0..16 {
-            values[i] = data[i];
+    }
+
+    /// Return a message's buffers to the pool
+    pub(crate) fn recycle(&mut self, msg: Message) {
+        match msg {
+            #[cfg(not(feature = "bounded-bulk"))]
+            Message::BulkCapacitanceMsg(msg) => self.bulk.put(msg.values),
+            #[cfg(feature = "alloc")]
+            Message::BulkCapacitanceWideMsg(msg) => self.bulk_wide.put(msg.values),
+            _ => (),
         }
-        Ok(Self{values})
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct BulkCapacitanceStruct {
-    pub start_index: u8,
-    pub values: Vec<u16>,
+/// Reusable storage for messages decoded by `Parser::parse_into`
+///
+/// Holds the last message decoded, and takes back its buffers for reuse once
+/// the next one completes, so a steady stream of bulk capacitance scans
+/// doesn't allocate for every frame. Other messages decode as usual.
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, Default)]
+pub struct MessageArena {
+    message: Option<Message>,
+    pub(crate) pool: DecodePool,
 }
 
-impl MessageStruct for BulkCapacitanceStruct {
-    fn id(&self) -> u8 {
-        BULK_CAPACITANCE_ID
+#[cfg(feature = "decode")]
+impl MessageArena {
+    pub fn new() -> MessageArena {
+        MessageArena::default()
     }
 
-    fn payload(&self) -> Vec<u8> {
-        let mut buf: Vec<u8> = Vec::with_capacity(self.values.len() * 2 + 2);
-        buf.push(self.start_index);
-        buf.push(self.values.len() as u8);
-        for x in &self.values {
-            buf.push((*x & 0xff) as u8);
-            buf.push((*x >> 8) as u8);
-        }
-        buf
+    /// The last message decoded into the arena
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_ref()
     }
 
-    fn message_size(data: &[u8]) -> Option<usize> {
-        // We don't know how long the message will be until we get the first byte
-        if data.len() < 2 {
-            None
-        } else {
-            Some((data[1] * 2 + 2) as usize)
+    /// Replace the stored message, recycling the old one
+    pub(crate) fn store(&mut self, msg: Message) -> &Message {
+        if let Some(old) = self.message.take() {
+            self.pool.recycle(old);
         }
+        self.message.insert(msg)
     }
+}
 
+/// A message decoded with a `DecoderRegistry`: either one this crate knows, or
+/// a custom one
+#[cfg(feature = "decode")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded<C> {
+    Message(Message),
+    Custom(C),
 }
 
-impl TryFrom<&[u8]> for BulkCapacitanceStruct {
-    type Error = ParseError;
+#[cfg(all(feature = "decode", feature = "alloc"))]
+type DecoderFn<C> = dyn Fn(&[u8]) -> Result<C, ParseError>;
 
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() < 2 {
-            return Err(ParseError::DeserializationError);
-        }
-        let start_index = data[0];
-        let count = data[1];
-        if data.len() < (2 + count * 2) as usize {
-            return Err(ParseError::DeserializationError);
-        }
-        let mut values: Vec<u16> = Vec::with_capacity(count as usize);
-        for i in 0..count {
-            let x: u16 = data[(i*2+2) as usize] as u16 + ((data[(i*2+3) as usize] as u16) << 8);
-            values.push(x);
-        }
-        Ok(Self{start_index, values})
-    }
+/// Decoders for vendor-specific message ids, producing a custom message type
+///
+/// Only consulted for ids this crate doesn't know. See
+/// `Parser::parse_with_registry`.
+#[cfg(all(feature = "decode", feature = "alloc"))]
+pub struct DecoderRegistry<C> {
+    decoders: Vec<(u8, Option<usize>, Box<DecoderFn<C>>)>,
 }
 
-#[derive(Debug, Clone)]
-pub struct ActiveCapacitanceStruct {
-    pub baseline: u16,
-    pub measurement: u16,
+#[cfg(all(feature = "decode", feature = "alloc"))]
+impl<C> Default for DecoderRegistry<C> {
+    fn default() -> Self {
+        DecoderRegistry{ decoders: Vec::new() }
+    }
 }
 
-impl MessageStruct for ActiveCapacitanceStruct {
-    fn id(&self) -> u8 {
-        ACTIVE_CAPACITANCE_ID
+#[cfg(all(feature = "decode", feature = "alloc"))]
+impl<C> DecoderRegistry<C> {
+    pub fn new() -> DecoderRegistry<C> {
+        DecoderRegistry::default()
     }
 
-    fn payload(&self) -> Vec<u8> {
-        let mut buf: Vec<u8> = Vec::with_capacity(4);
-        buf.push((self.baseline & 0xff) as u8);
-        buf.push((self.baseline >> 8) as u8);
-        buf.push((self.measurement & 0xff) as u8);
-        buf.push((self.measurement >> 8) as u8);
-        buf
+    /// Decode payloads for `id` with `decoder`, replacing any decoder already
+    /// registered for it
+    ///
+    /// `payload_len` is needed to delimit byte-stuffed frames; pass None for
+    /// variable length payloads, which then need explicitly framed input.
+    pub fn register<F>(&mut self, id: u8, payload_len: Option<usize>, decoder: F)
+    where
+        F: Fn(&[u8]) -> Result<C, ParseError> + 'static
+    {
+        self.decoders.retain(|(x, _, _)| *x != id);
+        self.decoders.push((id, payload_len, Box::new(decoder)));
     }
 
-    fn message_size(_data: &[u8]) -> Option<usize> {
-        Some(4)
+    /// Payload length registered for `id`
+    pub fn payload_len(&self, id: u8) -> Option<usize> {
+        self.decoders.iter().find(|(x, _, _)| *x == id).and_then(|(_, len, _)| *len)
+    }
+
+    /// Decode as `Message::from_payload`, falling back to the registered
+    /// decoders for unknown ids
+    pub fn decode(&self, id: u8, data: &[u8]) -> Result<Decoded<C>, ParseError> {
+        match Message::from_payload(id, data) {
+            Err(ParseError::UnknownPacketId(_)) => match self.decoders.iter().find(|(x, _, _)| *x == id) {
+                Some((_, _, decoder)) => decoder(data).map(Decoded::Custom),
+                None => Err(ParseError::UnknownPacketId(id)),
+            },
+            result => result.map(Decoded::Message),
+        }
     }
 }
 
-impl TryFrom<&[u8]> for ActiveCapacitanceStruct {
+impl TryFrom<(u8, &[u8])> for Message {
     type Error = ParseError;
 
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() < 4 {
-            return Err(ParseError::DeserializationError);
-        }
-        let baseline = data[0] as u16 + ((data[1] as u16) << 8);
-        let measurement = data[2] as u16 + ((data[3] as u16) << 8);
-        Ok(Self{baseline, measurement})
+    /// Decode an `(id, payload)` pair, as with `Message::from_payload`
+    fn try_from((id, data): (u8, &[u8])) -> Result<Self, Self::Error> {
+        Message::from_payload(id, data)
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct MoveStepperStruct {
-    pub steps: i16,
-    pub period: u16,
+impl From<ElectrodeEnableStruct> for Message {
+    fn from(msg: ElectrodeEnableStruct) -> Self {
+        Message::ElectrodeEnableMsg(msg)
+    }
 }
 
-impl MessageStruct for MoveStepperStruct {
-    fn id(&self) -> u8 {
-        MOVE_STEPPER_ID
+#[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+impl From<BulkCapacitanceStruct> for Message {
+    fn from(msg: BulkCapacitanceStruct) -> Self {
+        Message::BulkCapacitanceMsg(msg)
     }
+}
 
-    fn payload(&self) -> Vec<u8> {
-        let mut buf: Vec<u8> = Vec::with_capacity(4);
+impl From<ActiveCapacitanceStruct> for Message {
+    fn from(msg: ActiveCapacitanceStruct) -> Self {
+        Message::ActiveCapacitanceMsg(msg)
+    }
+}
 
-        buf.push((self.steps & 0xff) as u8);
-        buf.push((self.steps >> 8) as u8);
-        buf.push((self.period & 0xff) as u8);
-        buf.push((self.period >> 8) as u8);
-        buf
+impl From<CommandAckStruct> for Message {
+    fn from(msg: CommandAckStruct) -> Self {
+        Message::CommandAckMsg(msg)
     }
+}
 
-    fn message_size(_data: &[u8]) -> Option<usize> {
-        Some(4)
+impl From<MoveStepperStruct> for Message {
+    fn from(msg: MoveStepperStruct) -> Self {
+        Message::MoveStepperMsg(msg)
     }
 }
 
-impl TryFrom<&[u8]> for MoveStepperStruct {
-    type Error = ParseError;
+impl From<VersionStruct> for Message {
+    fn from(msg: VersionStruct) -> Self {
+        Message::VersionMsg(msg)
+    }
+}
 
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        if data.len() < 4 {
-            return Err(ParseError::DeserializationError);
-        }
-        let steps = (data[0] as u16 + ((data[1] as u16) << 8)) as i16;
-        let period = data[2] as u16 + ((data[3] as u16) << 8);
-        Ok(Self{steps, period})
+#[cfg(feature = "alloc")]
+impl From<BulkCapacitanceWideStruct> for Message {
+    fn from(msg: BulkCapacitanceWideStruct) -> Self {
+        Message::BulkCapacitanceWideMsg(msg)
     }
 }
 
-#[cfg(test)]
-mod tests {
+impl From<ConfigGetStruct> for Message {
+    fn from(msg: ConfigGetStruct) -> Self {
+        Message::ConfigGetMsg(msg)
+    }
+}
 
-    #[test]
-    fn active_capacitance_deser() {
-        use crate::*;
-        let bytes = &[0x10, 0x11, 0x12, 0x13];
-        let message = Message::from_payload(ACTIVE_CAPACITANCE_ID, bytes);
-        assert!(message.is_ok());
-        let message = message.unwrap();
-        match message {
-            Message::ActiveCapacitanceMsg(msg) => {
-                assert_eq!(0x1110, msg.baseline);
-                assert_eq!(0x1312, msg.measurement);
-            },
-            _ => panic!("Wrong kind of message")
-        }
+impl From<ConfigSetStruct> for Message {
+    fn from(msg: ConfigSetStruct) -> Self {
+        Message::ConfigSetMsg(msg)
     }
+}
 
-    #[test]
-    fn test_bulk_capacitance_deser() {
-        use crate::*;
-        let bytes = &[0, 2, 4, 0, 5, 0];
-        let message = Message::from_payload(BULK_CAPACITANCE_ID, bytes);
-        assert!(message.is_ok());
-        let message = message.unwrap();
-        match message {
-            Message::BulkCapacitanceMsg(msg) => {
-                assert_eq!(msg.start_index, 0);
-                assert_eq!(msg.values.len(), 2);
-                assert_eq!(msg.values[0], 4);
-                assert_eq!(msg.values[1], 5);
-            },
-            _ => panic!("Wrong kind of message"),
-        }
+impl From<RebootStruct> for Message {
+    fn from(msg: RebootStruct) -> Self {
+        Message::RebootMsg(msg)
     }
-    #[test]
-    fn test_bulk_capacitance_ser() {
-        use crate::*;
-        let expected_bytes = &[8, 2, 4, 0, 5, 0];
-        let message = BulkCapacitanceStruct{start_index: 8, values: vec![4, 5]};
-        let bytes: Vec<u8> = message.payload();
-        assert_eq!(bytes, expected_bytes);
+}
+
+#[cfg(feature = "alloc")]
+impl From<MoveStepperGroupStruct> for Message {
+    fn from(msg: MoveStepperGroupStruct) -> Self {
+        Message::MoveStepperGroupMsg(msg)
     }
+}
 
-    #[test]
-    fn test_electrode_enable_deser() {
-        use crate::*;
-        let bytes = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
-        let message = Message::from_payload(ELECTRODE_ENABLE_ID, bytes);
-        assert!(message.is_ok());
-        let message = message.unwrap();
-        match message {
-            Message::ElectrodeEnableMsg(msg) => {
-                for i in 0..16 {
-                    assert_eq!(msg.values[i], i as u8);
-                }
-            },
-            _ => panic!("Wrong kind of message"),
-        }
+impl From<FaultStruct> for Message {
+    fn from(msg: FaultStruct) -> Self {
+        Message::FaultMsg(msg)
     }
+}
 
-    #[test]
-    fn test_electrode_en_ser() {
-        use crate::*;
-        let expected_bytes = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
-        let message = ElectrodeEnableStruct{values: *expected_bytes};
-        let bytes: Vec<u8> = message.payload();
-        assert_eq!(bytes, expected_bytes);
+impl From<DriveParamsStruct> for Message {
+    fn from(msg: DriveParamsStruct) -> Self {
+        Message::DriveParamsMsg(msg)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<LabelStruct> for Message {
+    fn from(msg: LabelStruct) -> Self {
+        Message::LabelMsg(msg)
     }
+}
 
-}
\ No newline at end of file
+impl fmt::Display for Message {
+    /// Render a compact one-line summary of the message, for logging
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Message::*;
+        match self {
+            ElectrodeEnableMsg(msg) => {
+                write!(f, "ElectrodeEnable values=")?;
+                for x in &msg.values {
+                    write!(f, "{:02x}", x)?;
+                }
+                Ok(())
+            },
+            #[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+            BulkCapacitanceMsg(msg) => {
+                write!(f, "BulkCap start={} count={} bytes={}",
+                    msg.start_index, msg.values.len(), msg.values.len() * 2 + 2)
+            },
+            ActiveCapacitanceMsg(msg) => {
+                write!(f, "ActiveCap baseline=0x{:x} meas=0x{:x}", msg.baseline, msg.measurement)
+            },
+            CommandAckMsg(msg) => {
+                write!(f, "CommandAck acked_id=0x{:x} status={}", msg.acked_id, msg.status)
+            },
+            MoveStepperMsg(msg) => {
+                write!(f, "MoveStepper steps={} period={}", msg.steps, msg.period)
+            },
+            VersionMsg(msg) => {
+                write!(f, "Version {}.{}.{}", msg.major, msg.minor, msg.patch)
+            },
+            #[cfg(feature = "alloc")]
+            BulkCapacitanceWideMsg(msg) => {
+                write!(f, "BulkCapWide start={} count={} bytes={}",
+                    msg.start_index, msg.values.len(), msg.values.len() * 4 + 2)
+            },
+            ConfigGetMsg(msg) => {
+                write!(f, "ConfigGet key=0x{:x}", msg.key)
+            },
+            ConfigSetMsg(msg) => {
+                write!(f, "ConfigSet key=0x{:x} value={}", msg.key, msg.value)
+            },
+            RebootMsg(msg) => {
+                write!(f, "Reboot magic=0x{:x}", msg.magic)
+            },
+            #[cfg(feature = "alloc")]
+            MoveStepperGroupMsg(msg) => {
+                write!(f, "MoveStepperGroup axes={}", msg.moves.len())
+            },
+            FaultMsg(msg) => {
+                write!(f, "Fault code={:?} detail={}", msg.fault_code(), msg.detail)
+            },
+            DriveParamsMsg(msg) => {
+                write!(f, "DriveParams freq={}Hz voltage={}mV", msg.frequency_hz, msg.voltage_mv)
+            },
+            #[cfg(feature = "alloc")]
+            LabelMsg(msg) => {
+                match msg.name_str() {
+                    Ok(name) => write!(f, "Label index={} name={:?}", msg.index, name),
+                    Err(_) => write!(f, "Label index={} bytes={}", msg.index, msg.name.len()),
+                }
+            },
+            #[cfg(feature = "alloc")]
+            RawMessage { id, payload } => {
+                write!(f, "Raw id=0x{:x} bytes={}", id, payload.len())
+            },
+        }
+    }
+}
+
+pub trait MessageStruct {
+    fn id(&self) -> u8;
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8>;
+
+    /// Write the message payload into `out`, returning the number of bytes written
+    ///
+    /// Returns `BufferTooSmall` if `out` cannot hold the full payload. The default
+    /// implementation copies from `payload()`; structs override it to avoid the
+    /// allocation.
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        let payload = self.payload();
+        if out.len() < payload.len() {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[..payload.len()].copy_from_slice(&payload);
+        Ok(payload.len())
+    }
+
+    /// Write the message payload into a fixed-capacity `heapless::Vec`,
+    /// returning `BufferTooSmall` if it doesn't fit
+    #[cfg(all(feature = "encode", feature = "heapless"))]
+    fn payload_heapless<const N: usize>(&self) -> Result<heapless::Vec<u8, N>, ParseError> {
+        let mut buf = heapless::Vec::new();
+        // Can't fail: the length requested is the capacity
+        let _ = buf.resize_default(N);
+        let len = self.payload_into(&mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Exact length of the payload, without encoding it
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        self.payload().len()
+    }
+
+    /// Upper bound on the number of bytes `serialize_msg` will produce for this
+    /// message
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        max_framed_len(self.payload().len())
+    }
+
+    /// Returns the size of the message payload if it is known,
+    /// or None if it cannot yet be determined (i.e. because it depends on
+    /// message content not yet recieved)
+    ///
+    /// `data` is the payload contents received so far, it may be a partial
+    /// message.
+    fn message_size(data: &[u8]) -> Option<usize>;
+
+    /// Size of the shortest payload which is still valid, for messages with a
+    /// shorter legacy form; the same as `message_size` for most messages
+    fn min_message_size(data: &[u8]) -> Option<usize> {
+        Self::message_size(data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandAckStruct {
+    pub acked_id: u8,
+    /// Result of the command: 0 for success, nonzero for a device-specific
+    /// error code
+    pub status: u8,
+}
+
+impl CommandAckStruct {
+    /// Payload length: the acked id and status
+    pub const SIZE: usize = fixed_payload_size(2);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+
+    /// Name of the acknowledged command, see `message_name`
+    pub fn acked_message_name(&self) -> &'static str {
+        message_name(self.acked_id)
+    }
+}
+
+impl MessageStruct for CommandAckStruct {
+    fn id(&self) -> u8 {
+        COMMAND_ACK_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        vec![self.acked_id, self.status]
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 2 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.acked_id;
+        out[1] = self.status;
+        Ok(2)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        2
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+
+    fn min_message_size(_data: &[u8]) -> Option<usize> {
+        // Older devices sent only the acked id
+        Some(1)
+    }
+}
+
+impl TryFrom<&[u8]> for CommandAckStruct {
+    type Error = ParseError;
+
+    /// Decode an ack, accepting the legacy 1-byte form with an implied status
+    /// of 0
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.is_empty() || data.len() > 2 {
+            return Err(ParseError::DeserializationError {
+                id: COMMAND_ACK_ID, expected: if data.is_empty() { 1 } else { 2 }, actual: data.len()
+            });
+        }
+        let status = if data.len() > 1 { data[1] } else { 0 };
+        Ok(Self{acked_id: data[0], status})
+    }
+}
+
+/// Host-side tracking of commands which are waiting for a `CommandAck`
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default)]
+pub struct PendingCommands {
+    ids: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl PendingCommands {
+    pub fn new() -> PendingCommands {
+        PendingCommands{ ids: Vec::new() }
+    }
+
+    /// Note that a command with message id `id` was sent
+    pub fn record(&mut self, id: u8) {
+        self.ids.push(id);
+    }
+
+    /// True if `ack` acknowledges an outstanding command
+    pub fn matches(&self, ack: &CommandAckStruct) -> bool {
+        self.ids.contains(&ack.acked_id)
+    }
+
+    /// Clear the oldest outstanding command acknowledged by `ack`, returning
+    /// false if there was none
+    pub fn acknowledge(&mut self, ack: &CommandAckStruct) -> bool {
+        match self.ids.iter().position(|id| *id == ack.acked_id) {
+            Some(i) => {
+                self.ids.remove(i);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Number of commands still waiting for an ack
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ElectrodeEnableStruct {
+    pub values: [u8; 16],
+}
+
+impl ElectrodeEnableStruct {
+    /// Payload length: one bit per electrode
+    pub const SIZE: usize = fixed_payload_size(16);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+
+    /// Pack one enable flag per electrode into the wire representation
+    ///
+    /// Electrode `n` is stored in bit `n % 8` of byte `n / 8` (LSB first).
+    pub fn from_bools(bits: &[bool; 128]) -> Self {
+        let mut values = [0u8; 16];
+        for (i, bit) in bits.iter().enumerate() {
+            if *bit {
+                values[i / 8] |= 1 << (i % 8);
+            }
+        }
+        Self{values}
+    }
+
+    /// Unpack the enable flag for each electrode, using the same bit order as
+    /// `from_bools`
+    pub fn to_bools(&self) -> [bool; 128] {
+        let mut bits = [false; 128];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            *bit = self.values[i / 8] & (1 << (i % 8)) != 0;
+        }
+        bits
+    }
+
+    /// True if any electrode's enable flag differs from `other`
+    pub fn changed_from(&self, other: &Self) -> bool {
+        self.values != other.values
+    }
+
+    /// Indices of the electrodes whose enable flag differs from `other`, in
+    /// ascending order
+    pub fn diff<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = usize> + 'a {
+        (0..128).filter(move |i| {
+            (self.values[i / 8] ^ other.values[i / 8]) & (1 << (i % 8)) != 0
+        })
+    }
+}
+
+impl MessageStruct for ElectrodeEnableStruct {
+    fn id(&self) -> u8 {
+        ELECTRODE_ENABLE_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        self.values[..].into()
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < Self::SIZE {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[..Self::SIZE].copy_from_slice(&self.values);
+        Ok(Self::SIZE)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        Self::SIZE
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for ElectrodeEnableStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != Self::SIZE {
+            return Err(ParseError::DeserializationError {
+                id: ELECTRODE_ENABLE_ID, expected: Self::SIZE, actual: data.len()
+            });
+        }
+        let mut values = [0u8; Self::SIZE];
+        values.copy_from_slice(data);
+        Ok(Self{values})
+    }
+}
+
+/// Electrode enable flags for boards with fewer electrodes, sent as `BYTES`
+/// bytes rather than the full 16
+///
+/// Uses the same id and bit order as `ElectrodeEnableStruct`. The parser
+/// expects 16-byte frames for that id unless told otherwise with
+/// `Parser::with_payload_size`, and returns shorter frames as a `RawMessage`
+/// to be decoded with `TryFrom` on the payload.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ElectrodeEnableN<const BYTES: usize> {
+    pub values: [u8; BYTES],
+}
+
+/// The full 128 electrode message, equivalent to `ElectrodeEnableStruct`
+pub type ElectrodeEnable128 = ElectrodeEnableN<16>;
+
+impl<const BYTES: usize> ElectrodeEnableN<BYTES> {
+    /// Payload length: one bit per electrode
+    pub const SIZE: usize = fixed_payload_size(BYTES);
+}
+
+impl<const BYTES: usize> MessageStruct for ElectrodeEnableN<BYTES> {
+    fn id(&self) -> u8 {
+        ELECTRODE_ENABLE_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        self.values[..].into()
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < BYTES {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[..BYTES].copy_from_slice(&self.values);
+        Ok(BYTES)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        BYTES
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl<const BYTES: usize> TryFrom<&[u8]> for ElectrodeEnableN<BYTES> {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != BYTES {
+            return Err(ParseError::DeserializationError {
+                id: ELECTRODE_ENABLE_ID, expected: BYTES, actual: data.len()
+            });
+        }
+        let mut values = [0u8; BYTES];
+        values.copy_from_slice(data);
+        Ok(Self{values})
+    }
+}
+
+impl From<ElectrodeEnable128> for Message {
+    fn from(msg: ElectrodeEnable128) -> Self {
+        Message::ElectrodeEnableMsg(ElectrodeEnableStruct{ values: msg.values })
+    }
+}
+
+#[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BulkCapacitanceStruct {
+    /// Electrode index of the first value
+    ///
+    /// The protocol only addresses electrodes 0 through 255; values which
+    /// would land past 255 are carried on the wire but skipped by the indexed
+    /// accessors rather than wrapping around.
+    pub start_index: u8,
+    pub values: BulkValues,
+}
+
+/// Storage for `BulkCapacitanceStruct::values`
+///
+/// With the `bounded-bulk` feature this is a `heapless::Vec` holding up to
+/// `BulkCapacitanceStruct::MAX_VALUES`, so decoding doesn't allocate, and
+/// payloads carrying more values are rejected. Use
+/// `BulkCapacitanceStruct::from_slice` to build one with either storage.
+#[cfg(all(feature = "alloc", not(feature = "bounded-bulk")))]
+pub type BulkValues = Vec<u16>;
+#[cfg(feature = "bounded-bulk")]
+pub type BulkValues = heapless::Vec<u16, { BulkCapacitanceStruct::MAX_VALUES }>;
+
+#[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+impl BulkCapacitanceStruct {
+    /// Payload bytes before the values: the start index and count
+    pub const HEADER_SIZE: usize = 2;
+
+    /// Most values `BulkValues` can hold with the `bounded-bulk` feature
+    #[cfg(feature = "bounded-bulk")]
+    pub const MAX_VALUES: usize = 64;
+
+    /// Build a message from a slice of values, whichever storage `BulkValues`
+    /// is
+    ///
+    /// Returns `PayloadTooLong` if the values don't fit in bounded storage.
+    pub fn from_slice(start_index: u8, values: &[u16]) -> Result<Self, ParseError> {
+        #[cfg(not(feature = "bounded-bulk"))]
+        let values = values.to_vec();
+        #[cfg(feature = "bounded-bulk")]
+        let values = BulkValues::from_slice(values).map_err(|_| ParseError::PayloadTooLong {
+            id: BULK_CAPACITANCE_ID,
+            expected: Self::HEADER_SIZE + Self::MAX_VALUES * 2,
+            actual: Self::HEADER_SIZE + values.len() * 2,
+        })?;
+        Ok(Self{start_index, values})
+    }
+
+    /// Iterate over `(electrode index, value)` pairs, where the electrode index
+    /// is `start_index` offset by the position of the value
+    ///
+    /// Electrode indices are a u8, so any values which would land past index
+    /// 255 are not yielded.
+    pub fn indexed(&self) -> impl Iterator<Item = (u8, u16)> + '_ {
+        let start = self.start_index as usize;
+        self.values.iter().enumerate().filter_map(move |(i, x)| {
+            u8::try_from(start + i).ok().map(|index| (index, *x))
+        })
+    }
+
+    /// Bitmap of the electrodes whose value is at least `threshold`, in the
+    /// same bit order as `ElectrodeEnableStruct`
+    ///
+    /// Electrodes past index 127 are ignored.
+    pub fn threshold_mask(&self, threshold: u16) -> [u8; 16] {
+        let mut mask = [0u8; 16];
+        for (index, x) in self.indexed() {
+            let index = index as usize;
+            if index < 128 && x >= threshold {
+                mask[index / 8] |= 1 << (index % 8);
+            }
+        }
+        mask
+    }
+
+    /// Encode the payload with values in the given byte order
+    #[cfg(feature = "encode")]
+    pub fn payload_endian(&self, endian: Endian) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; Self::HEADER_SIZE + self.values.len() * 2];
+        // Can't fail, the buffer is exactly the payload length
+        let _ = self.payload_into_endian(&mut buf, endian);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn payload_into_endian(&self, out: &mut [u8], endian: Endian) -> Result<usize, ParseError> {
+        let len = Self::HEADER_SIZE + self.values.len() * 2;
+        if out.len() < len {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.start_index;
+        out[1] = self.values.len() as u8;
+        for (i, x) in self.values.iter().enumerate() {
+            write_u16(&mut out[Self::HEADER_SIZE + i * 2..], *x, endian);
+        }
+        Ok(len)
+    }
+
+    /// Decode a payload with values in the given byte order
+    pub fn from_payload_endian(data: &[u8], endian: Endian) -> Result<Self, ParseError> {
+        let mut msg = Self{start_index: 0, values: BulkValues::new()};
+        msg.decode_into(data, endian)?;
+        Ok(msg)
+    }
+
+    /// Decode a payload in place, reusing the capacity of `values`
+    ///
+    /// On error the struct is left unchanged.
+    pub fn decode_into(&mut self, data: &[u8], endian: Endian) -> Result<(), ParseError> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(ParseError::DeserializationError {
+                id: BULK_CAPACITANCE_ID, expected: Self::HEADER_SIZE, actual: data.len()
+            });
+        }
+        let count = data[1] as usize;
+        #[cfg(feature = "bounded-bulk")]
+        if count > Self::MAX_VALUES {
+            return Err(ParseError::PayloadTooLong {
+                id: BULK_CAPACITANCE_ID, expected: Self::HEADER_SIZE + Self::MAX_VALUES * 2, actual: data.len()
+            });
+        }
+        let expected = Self::HEADER_SIZE + count * 2;
+        if data.len() < expected {
+            return Err(ParseError::DeserializationError {
+                id: BULK_CAPACITANCE_ID, expected, actual: data.len()
+            });
+        }
+        self.start_index = data[0];
+        self.values.clear();
+        self.values.extend((0..count).map(|i| read_u16(&data[Self::HEADER_SIZE + i * 2..], endian)));
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+impl MessageStruct for BulkCapacitanceStruct {
+    fn id(&self) -> u8 {
+        BULK_CAPACITANCE_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        self.payload_endian(Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        self.payload_into_endian(out, Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        Self::HEADER_SIZE + self.values.len() * 2
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        max_framed_len(Self::HEADER_SIZE + self.values.len() * 2)
+    }
+
+    fn message_size(data: &[u8]) -> Option<usize> {
+        // We don't know how long the message will be until we get the first byte
+        if data.len() < Self::HEADER_SIZE {
+            None
+        } else {
+            Some(Self::HEADER_SIZE + data[1] as usize * 2)
+        }
+    }
+
+}
+
+#[cfg(any(feature = "alloc", feature = "bounded-bulk"))]
+impl TryFrom<&[u8]> for BulkCapacitanceStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_payload_endian(data, Endian::Little)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ActiveCapacitanceStruct {
+    pub baseline: u16,
+    pub measurement: u16,
+}
+
+impl ActiveCapacitanceStruct {
+    /// Payload length: two 16-bit fields
+    pub const SIZE: usize = fixed_payload_size(4);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+
+    /// Measurement relative to baseline, which may be negative
+    pub fn net(&self) -> i32 {
+        self.measurement as i32 - self.baseline as i32
+    }
+
+    /// Measurement as a fraction of baseline, or None if baseline is zero
+    pub fn ratio(&self) -> Option<f32> {
+        if self.baseline == 0 {
+            None
+        } else {
+            Some(self.measurement as f32 / self.baseline as f32)
+        }
+    }
+
+    /// Encode the payload with fields in the given byte order
+    #[cfg(feature = "encode")]
+    pub fn payload_endian(&self, endian: Endian) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; Self::SIZE];
+        let _ = self.payload_into_endian(&mut buf, endian);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn payload_into_endian(&self, out: &mut [u8], endian: Endian) -> Result<usize, ParseError> {
+        if out.len() < Self::SIZE {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u16(&mut out[0..], self.baseline, endian);
+        write_u16(&mut out[2..], self.measurement, endian);
+        Ok(Self::SIZE)
+    }
+
+    /// Decode a payload with fields in the given byte order
+    pub fn from_payload_endian(data: &[u8], endian: Endian) -> Result<Self, ParseError> {
+        if data.len() != Self::SIZE {
+            return Err(ParseError::DeserializationError {
+                id: ACTIVE_CAPACITANCE_ID, expected: Self::SIZE, actual: data.len()
+            });
+        }
+        let baseline = read_u16(&data[0..], endian);
+        let measurement = read_u16(&data[2..], endian);
+        Ok(Self{baseline, measurement})
+    }
+}
+
+impl MessageStruct for ActiveCapacitanceStruct {
+    fn id(&self) -> u8 {
+        ACTIVE_CAPACITANCE_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        self.payload_endian(Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        self.payload_into_endian(out, Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        Self::SIZE
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for ActiveCapacitanceStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_payload_endian(data, Endian::Little)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MoveStepperStruct {
+    pub steps: i16,
+    pub period: u16,
+}
+
+impl MoveStepperStruct {
+    /// Payload length: two 16-bit fields
+    pub const SIZE: usize = fixed_payload_size(4);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+
+    /// Encode the payload with fields in the given byte order
+    #[cfg(feature = "encode")]
+    pub fn payload_endian(&self, endian: Endian) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; Self::SIZE];
+        let _ = self.payload_into_endian(&mut buf, endian);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    pub fn payload_into_endian(&self, out: &mut [u8], endian: Endian) -> Result<usize, ParseError> {
+        if out.len() < Self::SIZE {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_i16(&mut out[0..], self.steps, endian);
+        write_u16(&mut out[2..], self.period, endian);
+        Ok(Self::SIZE)
+    }
+
+    /// Decode a payload with fields in the given byte order
+    pub fn from_payload_endian(data: &[u8], endian: Endian) -> Result<Self, ParseError> {
+        if data.len() != Self::SIZE {
+            return Err(ParseError::DeserializationError {
+                id: MOVE_STEPPER_ID, expected: Self::SIZE, actual: data.len()
+            });
+        }
+        let steps = read_i16(&data[0..], endian);
+        let period = read_u16(&data[2..], endian);
+        Ok(Self{steps, period})
+    }
+}
+
+impl MessageStruct for MoveStepperStruct {
+    fn id(&self) -> u8 {
+        MOVE_STEPPER_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        self.payload_endian(Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        self.payload_into_endian(out, Endian::Little)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        Self::SIZE
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for MoveStepperStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_payload_endian(data, Endian::Little)
+    }
+}
+
+/// Protocol version reported by the device, for the host to check
+/// compatibility before sending commands
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VersionStruct {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl VersionStruct {
+    /// Payload length: major, minor and patch
+    pub const SIZE: usize = fixed_payload_size(3);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+}
+
+impl MessageStruct for VersionStruct {
+    fn id(&self) -> u8 {
+        VERSION_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        vec![self.major, self.minor, self.patch]
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 3 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.major;
+        out[1] = self.minor;
+        out[2] = self.patch;
+        Ok(3)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        3
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for VersionStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 3 {
+            return Err(ParseError::DeserializationError {
+                id: VERSION_ID, expected: 3, actual: data.len()
+            });
+        }
+        Ok(Self{major: data[0], minor: data[1], patch: data[2]})
+    }
+}
+
+/// Bulk capacitance scan with 32-bit values, for sensors whose counts don't
+/// fit in a u16
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BulkCapacitanceWideStruct {
+    pub start_index: u8,
+    pub values: Vec<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl MessageStruct for BulkCapacitanceWideStruct {
+    fn id(&self) -> u8 {
+        BULK_CAPACITANCE_WIDE_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; self.values.len() * 4 + 2];
+        buf[0] = self.start_index;
+        buf[1] = self.values.len() as u8;
+        for (i, x) in self.values.iter().enumerate() {
+            write_u32_le(&mut buf[i * 4 + 2..], *x);
+        }
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        let len = self.values.len() * 4 + 2;
+        if out.len() < len {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.start_index;
+        out[1] = self.values.len() as u8;
+        for (i, x) in self.values.iter().enumerate() {
+            write_u32_le(&mut out[i * 4 + 2..], *x);
+        }
+        Ok(len)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        self.values.len() * 4 + 2
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        max_framed_len(self.values.len() * 4 + 2)
+    }
+
+    fn message_size(data: &[u8]) -> Option<usize> {
+        // We don't know how long the message will be until we get the count byte
+        if data.len() < 2 {
+            None
+        } else {
+            Some(data[1] as usize * 4 + 2)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<&[u8]> for BulkCapacitanceWideStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let mut msg = Self{start_index: 0, values: Vec::new()};
+        msg.decode_into(data)?;
+        Ok(msg)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl BulkCapacitanceWideStruct {
+    /// Decode a payload in place, reusing the capacity of `values`
+    ///
+    /// On error the struct is left unchanged.
+    pub fn decode_into(&mut self, data: &[u8]) -> Result<(), ParseError> {
+        if data.len() < 2 {
+            return Err(ParseError::DeserializationError {
+                id: BULK_CAPACITANCE_WIDE_ID, expected: 2, actual: data.len()
+            });
+        }
+        let count = data[1] as usize;
+        let expected = 2 + count * 4;
+        if data.len() < expected {
+            return Err(ParseError::DeserializationError {
+                id: BULK_CAPACITANCE_WIDE_ID, expected, actual: data.len()
+            });
+        }
+        self.start_index = data[0];
+        self.values.clear();
+        self.values.extend(data[2..expected].chunks_exact(4).map(read_u32_le));
+        Ok(())
+    }
+}
+
+/// Request for the current value of a device parameter, such as PWM frequency
+/// or sample rate
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigGetStruct {
+    pub key: u16,
+}
+
+impl ConfigGetStruct {
+    /// Payload length: a 16-bit key
+    pub const SIZE: usize = fixed_payload_size(2);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+}
+
+impl MessageStruct for ConfigGetStruct {
+    fn id(&self) -> u8 {
+        CONFIG_GET_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; 2];
+        write_u16_le(&mut buf, self.key);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 2 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u16_le(out, self.key);
+        Ok(2)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        2
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for ConfigGetStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 2 {
+            return Err(ParseError::DeserializationError {
+                id: CONFIG_GET_ID, expected: 2, actual: data.len()
+            });
+        }
+        Ok(Self{key: read_u16_le(data)})
+    }
+}
+
+/// Write a new value to a device parameter
+///
+/// Also sent by the device in reply to a `ConfigGet`, carrying the current value
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigSetStruct {
+    pub key: u16,
+    pub value: u32,
+}
+
+impl ConfigSetStruct {
+    /// Payload length: a 16-bit key and a 32-bit value
+    pub const SIZE: usize = fixed_payload_size(6);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+}
+
+impl MessageStruct for ConfigSetStruct {
+    fn id(&self) -> u8 {
+        CONFIG_SET_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; 6];
+        write_u16_le(&mut buf[0..], self.key);
+        write_u32_le(&mut buf[2..], self.value);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 6 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u16_le(&mut out[0..], self.key);
+        write_u32_le(&mut out[2..], self.value);
+        Ok(6)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        6
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for ConfigSetStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 6 {
+            return Err(ParseError::DeserializationError {
+                id: CONFIG_SET_ID, expected: 6, actual: data.len()
+            });
+        }
+        let key = read_u16_le(&data[0..]);
+        let value = read_u32_le(&data[2..]);
+        Ok(Self{key, value})
+    }
+}
+
+/// Command the device to perform a controlled reboot
+///
+/// The payload must carry `RebootStruct::MAGIC`, so that a corrupted or
+/// misrouted frame can't reset the device by accident.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RebootStruct {
+    pub magic: u32,
+}
+
+impl RebootStruct {
+    pub const MAGIC: u32 = 0xDEADBEEF;
+    /// Payload length: the 32-bit magic
+    pub const SIZE: usize = fixed_payload_size(4);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+}
+
+impl MessageStruct for RebootStruct {
+    fn id(&self) -> u8 {
+        REBOOT_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; 4];
+        write_u32_le(&mut buf, self.magic);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 4 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u32_le(out, self.magic);
+        Ok(4)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        4
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for RebootStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 4 {
+            return Err(ParseError::DeserializationError {
+                id: REBOOT_ID, expected: 4, actual: data.len()
+            });
+        }
+        let magic = read_u32_le(data);
+        if magic != Self::MAGIC {
+            return Err(ParseError::InvalidValue { id: REBOOT_ID });
+        }
+        Ok(Self{magic})
+    }
+}
+
+/// Moves for several steppers, to be started together
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MoveStepperGroupStruct {
+    /// (axis, steps, period) for each stepper, with fields as in `MoveStepperStruct`
+    pub moves: Vec<(u8, i16, u16)>,
+}
+
+#[cfg(feature = "alloc")]
+impl MessageStruct for MoveStepperGroupStruct {
+    fn id(&self) -> u8 {
+        MOVE_STEPPER_GROUP_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; self.moves.len() * 5 + 1];
+        buf[0] = self.moves.len() as u8;
+        for (i, (axis, steps, period)) in self.moves.iter().enumerate() {
+            buf[i * 5 + 1] = *axis;
+            write_i16_le(&mut buf[i * 5 + 2..], *steps);
+            write_u16_le(&mut buf[i * 5 + 4..], *period);
+        }
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        let len = self.moves.len() * 5 + 1;
+        if out.len() < len {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.moves.len() as u8;
+        for (i, (axis, steps, period)) in self.moves.iter().enumerate() {
+            out[i * 5 + 1] = *axis;
+            write_i16_le(&mut out[i * 5 + 2..], *steps);
+            write_u16_le(&mut out[i * 5 + 4..], *period);
+        }
+        Ok(len)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        self.moves.len() * 5 + 1
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        max_framed_len(self.moves.len() * 5 + 1)
+    }
+
+    fn message_size(data: &[u8]) -> Option<usize> {
+        // We don't know how long the message will be until we get the count byte
+        if data.is_empty() {
+            None
+        } else {
+            Some(data[0] as usize * 5 + 1)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<&[u8]> for MoveStepperGroupStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.is_empty() {
+            return Err(ParseError::DeserializationError {
+                id: MOVE_STEPPER_GROUP_ID, expected: 1, actual: 0
+            });
+        }
+        let count = data[0] as usize;
+        let expected = 1 + count * 5;
+        if data.len() < expected {
+            return Err(ParseError::DeserializationError {
+                id: MOVE_STEPPER_GROUP_ID, expected, actual: data.len()
+            });
+        }
+        let moves = data[1..expected]
+            .chunks_exact(5)
+            .map(|x| (x[0], read_i16_le(&x[1..]), read_u16_le(&x[3..])))
+            .collect();
+        Ok(Self{moves})
+    }
+}
+
+/// Known fault codes reported in `FaultStruct`
+///
+/// Every code converts, with unrecognized ones kept as `Unknown`, so this
+/// implements `From<u16>` rather than a fallible conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultCode {
+    OverTemperature,
+    OverCurrent,
+    UnderVoltage,
+    /// A code this crate doesn't recognize
+    Unknown(u16),
+}
+
+impl From<u16> for FaultCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => FaultCode::OverTemperature,
+            2 => FaultCode::OverCurrent,
+            3 => FaultCode::UnderVoltage,
+            x => FaultCode::Unknown(x),
+        }
+    }
+}
+
+impl From<FaultCode> for u16 {
+    fn from(code: FaultCode) -> Self {
+        match code {
+            FaultCode::OverTemperature => 1,
+            FaultCode::OverCurrent => 2,
+            FaultCode::UnderVoltage => 3,
+            FaultCode::Unknown(x) => x,
+        }
+    }
+}
+
+/// Unsolicited notification of a device fault
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FaultStruct {
+    pub code: u16,
+    /// Fault-specific detail, e.g. the measured temperature or current
+    pub detail: u32,
+}
+
+impl FaultStruct {
+    /// Payload length: a 16-bit code and 32-bit detail
+    pub const SIZE: usize = fixed_payload_size(6);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+
+    pub fn fault_code(&self) -> FaultCode {
+        FaultCode::from(self.code)
+    }
+}
+
+impl MessageStruct for FaultStruct {
+    fn id(&self) -> u8 {
+        FAULT_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; 6];
+        write_u16_le(&mut buf[0..], self.code);
+        write_u32_le(&mut buf[2..], self.detail);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 6 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u16_le(&mut out[0..], self.code);
+        write_u32_le(&mut out[2..], self.detail);
+        Ok(6)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        6
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for FaultStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 6 {
+            return Err(ParseError::DeserializationError {
+                id: FAULT_ID, expected: 6, actual: data.len()
+            });
+        }
+        let code = read_u16_le(&data[0..]);
+        let detail = read_u32_le(&data[2..]);
+        Ok(Self{code, detail})
+    }
+}
+
+/// Electrowetting drive settings
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DriveParamsStruct {
+    /// Drive frequency, which must be nonzero
+    pub frequency_hz: u32,
+    pub voltage_mv: u16,
+}
+
+impl DriveParamsStruct {
+    /// Payload length: a 32-bit frequency and 16-bit voltage
+    pub const SIZE: usize = fixed_payload_size(6);
+    pub const SERIALIZED_MAX_LEN: usize = max_framed_len(Self::SIZE);
+}
+
+impl MessageStruct for DriveParamsStruct {
+    fn id(&self) -> u8 {
+        DRIVE_PARAMS_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![0; 6];
+        write_u32_le(&mut buf[0..], self.frequency_hz);
+        write_u16_le(&mut buf[4..], self.voltage_mv);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        if out.len() < 6 {
+            return Err(ParseError::BufferTooSmall);
+        }
+        write_u32_le(&mut out[0..], self.frequency_hz);
+        write_u16_le(&mut out[4..], self.voltage_mv);
+        Ok(6)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        6
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        Self::SERIALIZED_MAX_LEN
+    }
+
+    fn message_size(_data: &[u8]) -> Option<usize> {
+        Some(Self::SIZE)
+    }
+}
+
+impl TryFrom<&[u8]> for DriveParamsStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 6 {
+            return Err(ParseError::DeserializationError {
+                id: DRIVE_PARAMS_ID, expected: 6, actual: data.len()
+            });
+        }
+        let frequency_hz = read_u32_le(&data[0..]);
+        let voltage_mv = read_u16_le(&data[4..]);
+        if frequency_hz == 0 {
+            return Err(ParseError::InvalidValue { id: DRIVE_PARAMS_ID });
+        }
+        Ok(Self{frequency_hz, voltage_mv})
+    }
+}
+
+/// Human-readable name for a channel, for display in a UI
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LabelStruct {
+    pub index: u8,
+    /// The name as sent, which should be UTF-8; see `name_str`
+    ///
+    /// Only the first `MAX_NAME_LEN` bytes are sent; use `new` to reject
+    /// longer names instead.
+    pub name: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl LabelStruct {
+    /// Longest name which fits in the parser's buffer, along with the index,
+    /// length byte and framing
+    pub const MAX_NAME_LEN: usize = super::MAX_MESSAGE_SIZE - 6;
+
+    /// Returns `InvalidValue` if the name is longer than `MAX_NAME_LEN`
+    pub fn new(index: u8, name: Vec<u8>) -> Result<Self, ParseError> {
+        if name.len() > Self::MAX_NAME_LEN {
+            return Err(ParseError::InvalidValue { id: LABEL_ID });
+        }
+        Ok(Self{index, name})
+    }
+
+    /// The part of the name which is sent
+    #[cfg(feature = "encode")]
+    fn sent_name(&self) -> &[u8] {
+        &self.name[..self.name.len().min(Self::MAX_NAME_LEN)]
+    }
+
+    /// The name as a string, or an error if it isn't valid UTF-8
+    pub fn name_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(&self.name)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl MessageStruct for LabelStruct {
+    fn id(&self) -> u8 {
+        LABEL_ID
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload(&self) -> Vec<u8> {
+        let name = self.sent_name();
+        let mut buf: Vec<u8> = Vec::with_capacity(name.len() + 2);
+        buf.push(self.index);
+        buf.push(name.len() as u8);
+        buf.extend_from_slice(name);
+        buf
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_into(&self, out: &mut [u8]) -> Result<usize, ParseError> {
+        let name = self.sent_name();
+        let len = name.len() + 2;
+        if out.len() < len {
+            return Err(ParseError::BufferTooSmall);
+        }
+        out[0] = self.index;
+        out[1] = name.len() as u8;
+        out[2..len].copy_from_slice(name);
+        Ok(len)
+    }
+
+    #[cfg(feature = "encode")]
+    fn payload_len(&self) -> usize {
+        self.sent_name().len() + 2
+    }
+
+    #[cfg(feature = "encode")]
+    fn serialized_max_len(&self) -> usize {
+        max_framed_len(self.payload_len())
+    }
+
+    fn message_size(data: &[u8]) -> Option<usize> {
+        // We don't know how long the message will be until we get the length byte
+        if data.len() < 2 {
+            None
+        } else {
+            Some(data[1] as usize + 2)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryFrom<&[u8]> for LabelStruct {
+    type Error = ParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < 2 {
+            return Err(ParseError::DeserializationError {
+                id: LABEL_ID, expected: 2, actual: data.len()
+            });
+        }
+        let index = data[0];
+        let expected = 2 + data[1] as usize;
+        if data.len() < expected {
+            return Err(ParseError::DeserializationError {
+                id: LABEL_ID, expected, actual: data.len()
+            });
+        }
+        Ok(Self{index, name: data[2..expected].into()})
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+
+    #[test]
+    fn active_capacitance_deser() {
+        use crate::*;
+        let bytes = &[0x10, 0x11, 0x12, 0x13];
+        let message = Message::from_payload(ACTIVE_CAPACITANCE_ID, bytes);
+        assert!(message.is_ok());
+        let message = message.unwrap();
+        match message {
+            Message::ActiveCapacitanceMsg(msg) => {
+                assert_eq!(0x1110, msg.baseline);
+                assert_eq!(0x1312, msg.measurement);
+            },
+            _ => panic!("Wrong kind of message")
+        }
+    }
+
+    #[test]
+    fn test_message_try_from_tuple() {
+        use crate::*;
+        use core::convert::TryFrom;
+        let bytes: &[u8] = &[2, 3, 4, 5];
+        let message = Message::try_from((ACTIVE_CAPACITANCE_ID, bytes)).unwrap();
+        assert_eq!(message, Message::ActiveCapacitanceMsg(ActiveCapacitanceStruct{baseline: 0x302, measurement: 0x504}));
+        assert!(Message::try_from((200, bytes)).is_err());
+    }
+
+    #[test]
+    fn test_message_kind() {
+        use crate::*;
+        let message = Message::MoveStepperMsg(MoveStepperStruct{steps: 1, period: 2});
+        assert_eq!(message.kind(), MessageKind::MoveStepper);
+        let message = Message::RawMessage { id: 200, payload: vec![] };
+        assert_eq!(message.kind(), MessageKind::Raw);
+    }
+
+    #[test]
+    fn test_active_capacitance_display() {
+        use crate::*;
+        let message = Message::ActiveCapacitanceMsg(ActiveCapacitanceStruct{baseline: 0x302, measurement: 0x504});
+        assert_eq!(format!("{}", message), "ActiveCap baseline=0x302 meas=0x504");
+    }
+
+    #[test]
+    fn test_command_ack_legacy_deser() {
+        use crate::*;
+        let message = Message::from_payload(COMMAND_ACK_ID, &[MOVE_STEPPER_ID]);
+        match message {
+            Ok(Message::CommandAckMsg(msg)) => {
+                assert_eq!(msg.acked_id, MOVE_STEPPER_ID);
+                assert_eq!(msg.status, 0);
+            },
+            _ => panic!("Wrong kind of message"),
+        }
+    }
+
+    #[test]
+    fn test_command_ack_status_deser() {
+        use crate::*;
+        let message = Message::from_payload(COMMAND_ACK_ID, &[MOVE_STEPPER_ID, 3]);
+        match message {
+            Ok(Message::CommandAckMsg(msg)) => {
+                assert_eq!(msg.acked_id, MOVE_STEPPER_ID);
+                assert_eq!(msg.status, 3);
+            },
+            _ => panic!("Wrong kind of message"),
+        }
+        let message = CommandAckStruct{acked_id: MOVE_STEPPER_ID, status: 3};
+        assert_eq!(message.payload(), vec![MOVE_STEPPER_ID, 3]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_struct_into_message() {
+        use crate::*;
+        let m: Message = ElectrodeEnableStruct{values: [0; 16]}.into();
+        assert!(matches!(m, Message::ElectrodeEnableMsg(_)));
+        let m: Message = BulkCapacitanceStruct{start_index: 0, values: vec![1]}.into();
+        assert!(matches!(m, Message::BulkCapacitanceMsg(_)));
+        let m: Message = ActiveCapacitanceStruct{baseline: 1, measurement: 2}.into();
+        assert!(matches!(m, Message::ActiveCapacitanceMsg(_)));
+        let m: Message = CommandAckStruct{acked_id: 1, status: 0}.into();
+        assert!(matches!(m, Message::CommandAckMsg(_)));
+        let m: Message = MoveStepperStruct{steps: -1, period: 2}.into();
+        assert!(matches!(m, Message::MoveStepperMsg(_)));
+        let m: Message = VersionStruct{major: 1, minor: 2, patch: 3}.into();
+        assert!(matches!(m, Message::VersionMsg(_)));
+        let m: Message = BulkCapacitanceWideStruct{start_index: 0, values: vec![1]}.into();
+        assert!(matches!(m, Message::BulkCapacitanceWideMsg(_)));
+        let m: Message = ConfigGetStruct{key: 1}.into();
+        assert!(matches!(m, Message::ConfigGetMsg(_)));
+        let m: Message = ConfigSetStruct{key: 1, value: 2}.into();
+        assert!(matches!(m, Message::ConfigSetMsg(_)));
+        let m: Message = RebootStruct{magic: RebootStruct::MAGIC}.into();
+        assert!(matches!(m, Message::RebootMsg(_)));
+        let m: Message = MoveStepperGroupStruct{moves: vec![]}.into();
+        assert!(matches!(m, Message::MoveStepperGroupMsg(_)));
+        let m: Message = FaultStruct{code: 1, detail: 0}.into();
+        assert!(matches!(m, Message::FaultMsg(_)));
+        let m: Message = DriveParamsStruct{frequency_hz: 1000, voltage_mv: 100}.into();
+        assert!(matches!(m, Message::DriveParamsMsg(_)));
+        let m: Message = LabelStruct{index: 0, name: vec![]}.into();
+        assert!(matches!(m, Message::LabelMsg(_)));
+    }
+
+    #[test]
+    fn test_active_capacitance_net_and_ratio() {
+        use crate::*;
+        let message = ActiveCapacitanceStruct{baseline: 200, measurement: 100};
+        assert_eq!(message.net(), -100);
+        assert_eq!(message.ratio(), Some(0.5));
+        let message = ActiveCapacitanceStruct{baseline: 0, measurement: 0xffff};
+        assert_eq!(message.net(), 0xffff);
+        assert_eq!(message.ratio(), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_message_hash_dedup() {
+        use crate::*;
+        use std::collections::HashSet;
+        let mut set = HashSet::new();
+        set.insert(Message::BulkCapacitanceMsg(BulkCapacitanceStruct{start_index: 0, values: vec![1, 2]}));
+        set.insert(Message::BulkCapacitanceMsg(BulkCapacitanceStruct{start_index: 0, values: vec![1, 2]}));
+        assert_eq!(set.len(), 1);
+        set.insert(Message::BulkCapacitanceMsg(BulkCapacitanceStruct{start_index: 1, values: vec![1, 2]}));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_bulk_capacitance_deser() {
+        use crate::*;
+        let bytes = &[0, 2, 4, 0, 5, 0];
+        let message = Message::from_payload(BULK_CAPACITANCE_ID, bytes);
+        assert!(message.is_ok());
+        let message = message.unwrap();
+        match message {
+            Message::BulkCapacitanceMsg(msg) => {
+                assert_eq!(msg.start_index, 0);
+                assert_eq!(msg.values.len(), 2);
+                assert_eq!(msg.values[0], 4);
+                assert_eq!(msg.values[1], 5);
+            },
+            _ => panic!("Wrong kind of message"),
+        }
+    }
+    #[test]
+    fn test_bulk_capacitance_short_deser() {
+        use crate::*;
+        // Claims 5 values but only has 1
+        let bytes = &[0, 5, 1, 0];
+        match Message::from_payload(BULK_CAPACITANCE_ID, bytes) {
+            Err(ParseError::DeserializationError { expected, actual, .. }) => {
+                assert_eq!(expected, 12);
+                assert_eq!(actual, 4);
+            },
+            other => panic!("Expected deserialization error, got {:?}", other),
+        }
+        assert_eq!(Message::message_size(BULK_CAPACITANCE_ID, &[0, 255]), Some(512));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_bulk_capacitance_ser() {
+        use crate::*;
+        let expected_bytes = &[8, 2, 4, 0, 5, 0];
+        let message = BulkCapacitanceStruct{start_index: 8, values: vec![4, 5]};
+        let bytes: Vec<u8> = message.payload();
+        assert_eq!(bytes, expected_bytes);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_bulk_capacitance_indexed() {
+        use crate::*;
+        let message = BulkCapacitanceStruct{start_index: 250, values: vec![10, 11, 12]};
+        let indexed: Vec<(u8, u16)> = message.indexed().collect();
+        assert_eq!(indexed, vec![(250, 10), (251, 11), (252, 12)]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_bulk_capacitance_indexed_wrap_boundary() {
+        use crate::*;
+        use core::convert::TryFrom;
+        // The second value would be electrode 256, which must not wrap to 0
+        let message = BulkCapacitanceStruct{start_index: 255, values: vec![10, 11]};
+        let indexed: Vec<(u8, u16)> = message.indexed().collect();
+        assert_eq!(indexed, vec![(255, 10)]);
+        assert_eq!(message.threshold_mask(0), [0; 16]);
+        // Skipped values still round-trip on the wire
+        let decoded = BulkCapacitanceStruct::try_from(&message.payload()[..]).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_version_roundtrip() {
+        use crate::*;
+        let message = VersionStruct{major: 1, minor: 2, patch: 3};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![1, 2, 3]);
+        assert_eq!(Message::from_payload(VERSION_ID, &bytes).unwrap(), Message::VersionMsg(message));
+        assert!(Message::from_payload(VERSION_ID, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_id_lenient_deser() {
+        use crate::*;
+        let bytes = &[9, 8, 7];
+        assert!(matches!(Message::from_payload(200, bytes), Err(ParseError::UnknownPacketId(200))));
+        let message = Message::from_payload_lenient(200, bytes).unwrap();
+        assert_eq!(message, Message::RawMessage { id: 200, payload: vec![9, 8, 7] });
+        // Known ids decode as usual
+        let message = Message::from_payload_lenient(VERSION_ID, &[1, 2, 3]).unwrap();
+        assert!(matches!(message, Message::VersionMsg(_)));
+    }
+
+    #[test]
+    fn test_bulk_capacitance_wide_roundtrip() {
+        use crate::*;
+        let message = BulkCapacitanceWideStruct{start_index: 4, values: vec![0x00123456, 0xfedcba98]};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![4, 2, 0x56, 0x34, 0x12, 0x00, 0x98, 0xba, 0xdc, 0xfe]);
+        assert_eq!(Message::message_size(BULK_CAPACITANCE_WIDE_ID, &bytes), Some(bytes.len()));
+        let decoded = Message::from_payload(BULK_CAPACITANCE_WIDE_ID, &bytes).unwrap();
+        assert_eq!(decoded, Message::BulkCapacitanceWideMsg(message));
+    }
+
+    #[test]
+    fn test_bulk_capacitance_wide_large_count() {
+        use crate::*;
+        let message = BulkCapacitanceWideStruct{start_index: 0, values: (0..200).collect()};
+        let bytes = message.payload();
+        assert_eq!(bytes.len(), 802);
+        assert_eq!(Message::message_size(BULK_CAPACITANCE_WIDE_ID, &bytes[..2]), Some(802));
+        let decoded = Message::from_payload(BULK_CAPACITANCE_WIDE_ID, &bytes).unwrap();
+        assert_eq!(decoded, Message::BulkCapacitanceWideMsg(message));
+    }
+
+    #[test]
+    fn test_electrode_enable_deser() {
+        use crate::*;
+        let bytes = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        let message = Message::from_payload(ELECTRODE_ENABLE_ID, bytes);
+        assert!(message.is_ok());
+        let message = message.unwrap();
+        match message {
+            Message::ElectrodeEnableMsg(msg) => {
+                for i in 0..16 {
+                    assert_eq!(msg.values[i], i as u8);
+                }
+            },
+            _ => panic!("Wrong kind of message"),
+        }
+    }
+
+    #[test]
+    fn test_electrode_en_payload_into() {
+        use crate::*;
+        let values = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0x7d, 0x7e];
+        let message = ElectrodeEnableStruct{values};
+        let mut buf = [0u8; 20];
+        let len = message.payload_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &message.payload()[..]);
+        assert!(message.payload_into(&mut buf[..15]).is_err());
+    }
+
+    #[test]
+    fn test_electrode_en_bools_roundtrip() {
+        use crate::*;
+        let mut bits = [false; 128];
+        bits[0] = true;
+        bits[9] = true;
+        bits[127] = true;
+        let message = ElectrodeEnableStruct::from_bools(&bits);
+        assert_eq!(message.values[0], 0x01);
+        assert_eq!(message.values[1], 0x02);
+        assert_eq!(message.values[15], 0x80);
+        assert_eq!(message.to_bools()[..], bits[..]);
+    }
+
+    #[test]
+    fn test_electrode_enable_short_deser() {
+        use crate::*;
+        let bytes = &[0u8; 10];
+        match Message::from_payload(ELECTRODE_ENABLE_ID, bytes) {
+            Err(ParseError::DeserializationError { id, expected, actual }) => {
+                assert_eq!(id, ELECTRODE_ENABLE_ID);
+                assert_eq!(expected, 16);
+                assert_eq!(actual, 10);
+            },
+            other => panic!("Expected deserialization error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_electrode_en_ser() {
+        use crate::*;
+        let expected_bytes = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        let message = ElectrodeEnableStruct{values: *expected_bytes};
+        let bytes: Vec<u8> = message.payload();
+        assert_eq!(bytes, expected_bytes);
+    }
+
+
+    #[test]
+    fn test_config_get_roundtrip() {
+        use crate::*;
+        let message = ConfigGetStruct{key: 0x0102};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![0x02, 0x01]);
+        assert_eq!(Message::from_payload(CONFIG_GET_ID, &bytes).unwrap(), Message::ConfigGetMsg(message));
+        assert!(Message::from_payload(CONFIG_GET_ID, &[1]).is_err());
+    }
+
+    #[test]
+    fn test_config_set_roundtrip() {
+        use crate::*;
+        let message = ConfigSetStruct{key: 7, value: 0x12345678};
+        let bytes = message.payload();
+        // value is little-endian after the key
+        assert_eq!(bytes, vec![7, 0, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(Message::from_payload(CONFIG_SET_ID, &bytes).unwrap(), Message::ConfigSetMsg(message));
+        assert!(Message::from_payload(CONFIG_SET_ID, &bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn test_reboot_magic() {
+        use crate::*;
+        let message = RebootStruct{magic: RebootStruct::MAGIC};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(Message::from_payload(REBOOT_ID, &bytes).unwrap(), Message::RebootMsg(message));
+        let bad = RebootStruct{magic: 0x12345678}.payload();
+        assert!(matches!(Message::from_payload(REBOOT_ID, &bad), Err(ParseError::InvalidValue { id: REBOOT_ID })));
+    }
+
+    #[test]
+    fn test_pending_commands() {
+        use crate::*;
+        let mut pending = PendingCommands::new();
+        pending.record(MOVE_STEPPER_ID);
+        let ack = CommandAckStruct{acked_id: MOVE_STEPPER_ID, status: 0};
+        assert!(!pending.matches(&CommandAckStruct{acked_id: REBOOT_ID, status: 0}));
+        assert!(pending.matches(&ack));
+        assert!(pending.acknowledge(&ack));
+        assert!(pending.is_empty());
+        assert!(!pending.acknowledge(&ack));
+    }
+
+    #[test]
+    fn test_move_stepper_group_roundtrip() {
+        use crate::*;
+        let message = MoveStepperGroupStruct{moves: vec![]};
+        assert_eq!(message.payload(), vec![0]);
+        assert_eq!(Message::from_payload(MOVE_STEPPER_GROUP_ID, &[0]).unwrap(), Message::MoveStepperGroupMsg(message));
+
+        let message = MoveStepperGroupStruct{moves: vec![(2, -2, 0x1234)]};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![1, 2, 0xfe, 0xff, 0x34, 0x12]);
+        assert_eq!(Message::from_payload(MOVE_STEPPER_GROUP_ID, &bytes).unwrap(), Message::MoveStepperGroupMsg(message));
+
+        let message = MoveStepperGroupStruct{moves: vec![(0, 100, 10), (1, -100, 20), (2, 0, 30)]};
+        let bytes = message.payload();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(Message::message_size(MOVE_STEPPER_GROUP_ID, &bytes), Some(16));
+        assert_eq!(Message::from_payload(MOVE_STEPPER_GROUP_ID, &bytes).unwrap(), Message::MoveStepperGroupMsg(message));
+        assert!(Message::from_payload(MOVE_STEPPER_GROUP_ID, &bytes[..15]).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_big_endian_roundtrip() {
+        use crate::*;
+        let message = ActiveCapacitanceStruct{baseline: 0x0102, measurement: 0x0304};
+        let bytes = message.payload_endian(Endian::Big);
+        assert_eq!(bytes, vec![1, 2, 3, 4]);
+        let decoded = Message::from_payload_endian(ACTIVE_CAPACITANCE_ID, &bytes, Endian::Big).unwrap();
+        assert_eq!(decoded, Message::ActiveCapacitanceMsg(message));
+
+        let message = MoveStepperStruct{steps: -2, period: 0x0102};
+        let bytes = message.payload_endian(Endian::Big);
+        assert_eq!(bytes, vec![0xff, 0xfe, 1, 2]);
+        let decoded = Message::from_payload_endian(MOVE_STEPPER_ID, &bytes, Endian::Big).unwrap();
+        assert_eq!(decoded, Message::MoveStepperMsg(message));
+
+        let message = BulkCapacitanceStruct{start_index: 3, values: vec![0x0102, 0x0304]};
+        let bytes = message.payload_endian(Endian::Big);
+        assert_eq!(bytes, vec![3, 2, 1, 2, 3, 4]);
+        let decoded = Message::from_payload_endian(BULK_CAPACITANCE_ID, &bytes, Endian::Big).unwrap();
+        assert_eq!(decoded, Message::BulkCapacitanceMsg(message.clone()));
+        // Little-endian remains the default
+        assert_eq!(message.payload(), message.payload_endian(Endian::Little));
+    }
+
+    #[test]
+    fn test_fault_roundtrip() {
+        use crate::*;
+        let message = FaultStruct{code: 2, detail: 0x01020304};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![2, 0, 4, 3, 2, 1]);
+        assert_eq!(Message::from_payload(FAULT_ID, &bytes).unwrap(), Message::FaultMsg(message));
+        assert!(Message::from_payload(FAULT_ID, &bytes[..4]).is_err());
+    }
+
+    #[test]
+    fn test_fault_code() {
+        use crate::*;
+        assert_eq!(FaultStruct{code: 1, detail: 0}.fault_code(), FaultCode::OverTemperature);
+        assert_eq!(FaultCode::from(2), FaultCode::OverCurrent);
+        assert_eq!(FaultCode::from(0x1234), FaultCode::Unknown(0x1234));
+        assert_eq!(u16::from(FaultCode::UnderVoltage), 3);
+    }
+
+    #[test]
+    fn test_acked_message_name() {
+        use crate::*;
+        assert_eq!(CommandAckStruct{acked_id: MOVE_STEPPER_ID, status: 0}.acked_message_name(), "MoveStepper");
+        assert_eq!(CommandAckStruct{acked_id: 200, status: 0}.acked_message_name(), "Unknown");
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_into_bytes() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct{start_index: 1, values: vec![0x7e, 0x1234]};
+        let expected = serialize_msg(&msg);
+        assert_eq!(Message::from(msg).into_bytes(), expected);
+        let raw = Message::RawMessage { id: 200, payload: vec![1, 2] };
+        assert_eq!(raw.into_bytes(), serialize_raw(200, &[1, 2]));
+    }
+
+    #[test]
+    fn test_drive_params_roundtrip() {
+        use crate::*;
+        let message = DriveParamsStruct{frequency_hz: 10_000, voltage_mv: 0x0102};
+        let bytes = message.payload();
+        assert_eq!(bytes, vec![0x10, 0x27, 0, 0, 2, 1]);
+        assert_eq!(Message::from_payload(DRIVE_PARAMS_ID, &bytes).unwrap(), Message::DriveParamsMsg(message));
+        let zero = DriveParamsStruct{frequency_hz: 0, voltage_mv: 100}.payload();
+        assert!(matches!(Message::from_payload(DRIVE_PARAMS_ID, &zero), Err(ParseError::InvalidValue { id: DRIVE_PARAMS_ID })));
+    }
+
+    #[test]
+    fn test_label_roundtrip() {
+        use crate::*;
+        let message = LabelStruct{index: 3, name: "Électrode µ".as_bytes().into()};
+        let bytes = message.payload();
+        assert_eq!(bytes[..2], [3, 13]);
+        assert_eq!(Message::message_size(LABEL_ID, &bytes), Some(15));
+        match Message::from_payload(LABEL_ID, &bytes).unwrap() {
+            Message::LabelMsg(decoded) => assert_eq!(decoded.name_str(), Ok("Électrode µ")),
+            _ => panic!("Wrong kind of message"),
+        }
+        assert_eq!(Message::from_payload(LABEL_ID, &bytes).unwrap(), Message::LabelMsg(message));
+        assert!(Message::from_payload(LABEL_ID, &bytes[..14]).is_err());
+    }
+
+    #[test]
+    fn test_label_name_limit() {
+        use crate::*;
+        let max = LabelStruct::MAX_NAME_LEN;
+        assert!(fits_in_buffer(max + 2));
+        assert!(matches!(LabelStruct::new(0, vec![b'a'; max + 1]), Err(ParseError::InvalidValue { id: LABEL_ID })));
+
+        // The longest name makes it through the parser
+        let message = LabelStruct::new(1, vec![b'a'; max]).unwrap();
+        let mut parser = Parser::new();
+        let mut out = Vec::new();
+        parser.feed(&serialize_msg(&message), &mut out).unwrap();
+        assert_eq!(out, vec![Message::LabelMsg(message.clone())]);
+        #[cfg(feature = "crc32")]
+        {
+            let mut parser = Parser::new().with_crc32(true);
+            let mut out = Vec::new();
+            parser.feed(&serialize_msg_crc32(&message), &mut out).unwrap();
+            assert_eq!(out, vec![Message::LabelMsg(message)]);
+        }
+
+        // Longer names set directly are cut to the limit, length byte included
+        let message = LabelStruct{index: 1, name: vec![b'a'; 300]};
+        let bytes = message.payload();
+        assert_eq!(bytes.len(), max + 2);
+        assert_eq!(bytes[1] as usize, max);
+        let mut buf = [0u8; 200];
+        assert_eq!(message.payload_into(&mut buf).unwrap(), max + 2);
+        assert_eq!(buf[..max + 2], bytes[..]);
+    }
+
+    #[test]
+    fn test_label_invalid_utf8() {
+        use crate::*;
+        let message = LabelStruct{index: 0, name: vec![b'a', 0xff, 0xfe]};
+        assert!(message.name_str().is_err());
+        assert_eq!(format!("{}", Message::LabelMsg(message)), "Label index=0 bytes=3");
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_from_payload_with_len() {
+        use crate::*;
+        let bytes = &[0, 3, 4, 0, 5, 0, 6, 0, 0xaa, 0xbb];
+        let (message, len) = Message::from_payload_with_len(BULK_CAPACITANCE_ID, bytes).unwrap();
+        assert_eq!(len, 2 + 3 * 2);
+        assert_eq!(message, Message::BulkCapacitanceMsg(BulkCapacitanceStruct{start_index: 0, values: vec![4, 5, 6]}));
+        let (_, len) = Message::from_payload_with_len(VERSION_ID, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(len, 3);
+        assert!(Message::from_payload_with_len(BULK_CAPACITANCE_ID, &bytes[..5]).is_err());
+    }
+
+    #[test]
+    fn test_electrode_enable_diff() {
+        use crate::*;
+        let mut bits = [false; 128];
+        bits[3] = true;
+        bits[40] = true;
+        let a = ElectrodeEnableStruct::from_bools(&bits);
+        bits[40] = false;
+        bits[95] = true;
+        let b = ElectrodeEnableStruct::from_bools(&bits);
+        assert!(a.changed_from(&b));
+        assert!(!a.changed_from(&a.clone()));
+        assert_eq!(a.diff(&b).collect::<Vec<_>>(), vec![40, 95]);
+        assert_eq!(a.diff(&a).count(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_bulk_capacitance_threshold_mask() {
+        use crate::*;
+        let message = BulkCapacitanceStruct{start_index: 6, values: vec![100, 5, 50, 49, 200]};
+        let mask = message.threshold_mask(50);
+        // Electrodes 6, 8 and 10
+        let mut expected = [0u8; 16];
+        expected[0] = 0x40;
+        expected[1] = 0x05;
+        assert_eq!(mask, expected);
+        let message = BulkCapacitanceStruct{start_index: 127, values: vec![1, 1, 1]};
+        let mut expected = [0u8; 16];
+        expected[15] = 0x80;
+        assert_eq!(message.threshold_mask(0), expected);
+    }
+
+    #[test]
+    fn test_fixed_four_byte_structs_reject_long_payload() {
+        use crate::*;
+        use core::convert::TryFrom;
+        let data = [1u8, 2, 3, 4, 5];
+        assert!(matches!(
+            ActiveCapacitanceStruct::try_from(&data[..]),
+            Err(ParseError::DeserializationError { id: ACTIVE_CAPACITANCE_ID, expected: 4, actual: 5 })
+        ));
+        assert!(matches!(
+            MoveStepperStruct::try_from(&data[..]),
+            Err(ParseError::DeserializationError { id: MOVE_STEPPER_ID, expected: 4, actual: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_all_ids() {
+        use crate::*;
+        assert_eq!(Message::ALL_IDS.len(), MessageId::ALL.len());
+        assert_eq!(Message::ALL_IDS.iter().filter(|id| **id == MOVE_STEPPER_ID).count(), 1);
+        for id in Message::ALL_IDS {
+            assert_eq!(MessageId::from_id(*id).map(MessageId::id), Some(*id));
+            assert_eq!(Message::id_name(*id), Some(message_name(*id)));
+        }
+        assert_eq!(Message::id_name(MOVE_STEPPER_ID), Some("MoveStepper"));
+        assert_eq!(Message::id_name(200), None);
+        assert!(!Message::ALL_IDS.contains(&DRIVE_ENABLE_ID));
+        assert_eq!(message_name(200), "Unknown");
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_payload_len() {
+        use crate::*;
+        let m: Message = BulkCapacitanceStruct{start_index: 0, values: vec![1, 2, 3]}.into();
+        assert_eq!(m.payload_len(), 8);
+        assert_eq!(m.payload_len(), m.payload().len());
+        let m: Message = ElectrodeEnableStruct{values: [0; 16]}.into();
+        assert_eq!(m.payload_len(), 16);
+        let m = Message::RawMessage{ id: 200, payload: vec![1, 2] };
+        assert_eq!(m.payload_len(), 2);
+    }
+
+    #[test]
+    fn test_message_id_ord() {
+        use crate::*;
+        use alloc::collections::BTreeMap;
+        let mut table = BTreeMap::new();
+        for id in &[MessageId::Label, MessageId::ElectrodeEnable, MessageId::MoveStepper] {
+            table.insert(*id, id.name());
+        }
+        let ids: Vec<MessageId> = table.keys().copied().collect();
+        assert_eq!(ids, vec![MessageId::ElectrodeEnable, MessageId::MoveStepper, MessageId::Label]);
+        assert!(MessageKind::ElectrodeEnable < MessageKind::Raw);
+    }
+
+    #[test]
+    fn test_split() {
+        use crate::*;
+        let mut values = [0u8; 16];
+        values[3] = 0x7e;
+        let msg: Message = ElectrodeEnableStruct{values}.into();
+        let (id, payload) = msg.clone().split();
+        assert_eq!(id, ELECTRODE_ENABLE_ID);
+        assert_eq!(Message::from_payload(id, &payload).unwrap(), msg);
+        let raw = Message::RawMessage{ id: 200, payload: vec![1, 2] };
+        assert_eq!(raw.split(), (200, vec![1, 2]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bounded-bulk"))]
+    fn test_size_consts() {
+        use crate::*;
+        assert_eq!(ElectrodeEnableStruct{values: [0; 16]}.payload().len(), ElectrodeEnableStruct::SIZE);
+        assert_eq!(ActiveCapacitanceStruct{baseline: 1, measurement: 2}.payload().len(), ActiveCapacitanceStruct::SIZE);
+        assert_eq!(MoveStepperStruct{steps: 1, period: 2}.payload().len(), MoveStepperStruct::SIZE);
+        let bulk = BulkCapacitanceStruct{start_index: 0, values: vec![1, 2, 3]};
+        assert_eq!(bulk.payload().len(), BulkCapacitanceStruct::HEADER_SIZE + 3 * 2);
+        let empty = BulkCapacitanceStruct{start_index: 0, values: vec![]};
+        assert_eq!(empty.payload().len(), BulkCapacitanceStruct::HEADER_SIZE);
+    }
+
+    #[test]
+    #[cfg(feature = "bounded-bulk")]
+    fn test_bulk_capacitance_bounded() {
+        use crate::*;
+        use core::convert::TryFrom;
+        let mut data = vec![0, 64];
+        for i in 0..64u16 {
+            data.extend(&i.to_le_bytes());
+        }
+        let msg = BulkCapacitanceStruct::try_from(&data[..]).unwrap();
+        assert_eq!(msg.values.len(), 64);
+        assert_eq!(msg.values[63], 63);
+        assert_eq!(msg.payload(), data);
+
+        let mut data = vec![0, 100];
+        data.resize(2 + 100 * 2, 0);
+        match BulkCapacitanceStruct::try_from(&data[..]) {
+            Err(ParseError::PayloadTooLong { id: BULK_CAPACITANCE_ID, expected: 130, actual: 202 }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let values: Vec<u16> = (0..64).collect();
+        assert_eq!(BulkCapacitanceStruct::from_slice(0, &values).unwrap(), msg);
+        assert!(matches!(
+            BulkCapacitanceStruct::from_slice(0, &[0; 65]),
+            Err(ParseError::PayloadTooLong { id: BULK_CAPACITANCE_ID, expected: 130, actual: 132 })
+        ));
+    }
+
+    #[test]
+    fn test_bulk_capacitance_from_slice() {
+        use crate::*;
+        let msg = BulkCapacitanceStruct::from_slice(8, &[4, 5]).unwrap();
+        assert_eq!(msg.start_index, 8);
+        assert_eq!(msg.values[..], [4, 5]);
+        assert_eq!(msg.payload(), vec![8, 2, 4, 0, 5, 0]);
+    }
+
+    #[test]
+    fn test_electrode_enable_n() {
+        use crate::*;
+        use core::convert::TryFrom;
+        let msg = ElectrodeEnableN::<4>{ values: [0x01, 0x7e, 0x00, 0x80] };
+        assert_eq!(msg.payload(), vec![0x01, 0x7e, 0x00, 0x80]);
+        assert_eq!(ElectrodeEnableN::<4>::message_size(&[]), Some(4));
+        let decoded = ElectrodeEnableN::<4>::try_from(&msg.payload()[..]).unwrap();
+        assert_eq!(decoded, msg);
+        // A 32 electrode frame is 12 bytes shorter than the full one
+        let full = ElectrodeEnableStruct{ values: [0; 16] };
+        assert_eq!(serialize_msg(&full).len() - serialize_msg(&ElectrodeEnableN::<4>{ values: [0; 4] }).len(), 12);
+        let frame = unescape(&serialize_msg(&msg)[1..]).unwrap();
+        assert_eq!(verify_frame(&frame).unwrap(), (ELECTRODE_ENABLE_ID, &msg.values[..]));
+
+        let mut parser = Parser::new().with_payload_size(ELECTRODE_ENABLE_ID, ElectrodeEnableN::<4>::SIZE);
+        let mut out = Vec::new();
+        parser.feed(&serialize_msg(&msg), &mut out).unwrap();
+        parser.feed(&serialize_msg(&msg), &mut out).unwrap();
+        assert_eq!(out.len(), 2);
+        for decoded in out {
+            match decoded {
+                Message::RawMessage { id: ELECTRODE_ENABLE_ID, payload } => {
+                    assert_eq!(ElectrodeEnableN::<4>::try_from(&payload[..]).unwrap(), msg);
+                },
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        // A full 16 byte frame no longer matches the expected size
+        let mut out = Vec::new();
+        parser.feed(&serialize_msg(&ElectrodeEnableStruct{ values: [1; 16] }), &mut out).unwrap_err();
+
+        assert!(matches!(
+            ElectrodeEnableN::<4>::try_from(&[0u8; 5][..]),
+            Err(ParseError::DeserializationError { id: ELECTRODE_ENABLE_ID, expected: 4, actual: 5 })
+        ));
+        let msg: Message = ElectrodeEnable128{ values: [3; 16] }.into();
+        assert_eq!(msg, Message::ElectrodeEnableMsg(ElectrodeEnableStruct{ values: [3; 16] }));
+    }
+
+    #[test]
+    fn test_from_payload_rejects_wrong_length() {
+        use crate::*;
+        // Electrode enable data passed with the wrong id
+        let electrodes = ElectrodeEnableStruct{ values: [0x11; 16] }.payload();
+        assert!(matches!(
+            Message::from_payload(ACTIVE_CAPACITANCE_ID, &electrodes),
+            Err(ParseError::DeserializationError { id: ACTIVE_CAPACITANCE_ID, expected: 4, actual: 16 })
+        ));
+        // Every message whose size is known before any payload arrives
+        let fixed = MessageId::ALL.iter().filter_map(|id| Message::message_size(*id as u8, &[]).map(|size| (*id as u8, size)));
+        for (id, size) in fixed.filter(|(_, size)| *size > 0) {
+            let data = vec![0x5a; size + 1];
+            assert!(matches!(
+                Message::from_payload(id, &data),
+                Err(ParseError::DeserializationError { id: err_id, actual, .. }) if err_id == id && actual == size + 1
+            ), "id {} accepted {} bytes", id, size + 1);
+            // Acks have a legacy 1-byte form
+            if id != COMMAND_ACK_ID {
+                assert!(Message::from_payload(id, &data[..size - 1]).is_err(), "id {} accepted {} bytes", id, size - 1);
+            }
+        }
+    }
+}